@@ -9,22 +9,21 @@ async fn main() -> Result<(), anyhow::Error> {
     // ---------- //
     // CONNECTION //
     // ---------- //
-    // Connect to the simulator.
-    let mut client = client::FSDSClient::init(None, None)
+    // Connect to the simulator, check the connection and enable control of
+    // the vehicle via the API.
+    let mut client = client::FSDSClient::connect_and_ready(None, VEHICLE_NAME)
         .await
         .expect("Cannot establish a connection with the simulator");
-    // Check network connection, exit if not connected.
-    client.ping().await?;
-    // Enable control of the vehicle via the API.
-    client.enable_api_control(VEHICLE_NAME).await?;
 
     // ---------------- //
     // CONTROL THE CAR! //
     // ---------------- //
     // Set the throttle to 1.0.
-    let mut controls = CarControls::default();
-    controls.throttle = 1.0;
-    client.set_car_controls(controls, VEHICLE_NAME).await;
+    let controls = CarControls {
+        throttle: 1.0,
+        ..Default::default()
+    };
+    client.set_car_controls(controls, VEHICLE_NAME).await?;
 
     // Loop to keep the program running.
     loop {
@@ -2,7 +2,109 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, Data, DataStruct, DeriveInput, Fields, FieldsNamed};
+use syn::{
+    parse_macro_input, Attribute, Data, DataEnum, DataStruct, DeriveInput, Fields, FieldsNamed,
+    Ident, Type,
+};
+
+/// Integer types which are decoded tolerantly, see [`from_and_into_for_value_derive`].
+const INTEGER_TYPES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize",
+];
+
+/// Returns true if `ty` is one of the primitive [`INTEGER_TYPES`].
+fn is_integer_type(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .get_ident()
+            .is_some_and(|ident| INTEGER_TYPES.contains(&ident.to_string().as_str())),
+        _ => false,
+    }
+}
+
+/// Expression converting `value: Value` to an `Option<i128>`, see the
+/// integer rules of [`from_and_into_for_value_derive`]. Used for both integer
+/// fields and enum discriminants.
+fn integer_from_value() -> proc_macro2::TokenStream {
+    quote! {
+        match &value {
+            Value::Integer(n) => n
+                .as_i64()
+                .map(i128::from)
+                .or(n.as_u64().map(i128::from)),
+            Value::F32(f) if f.is_finite() => Some(f.round() as i128),
+            Value::F64(f) if f.is_finite() => Some(f.round() as i128),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if `attrs` contain `#[fsds(default)]`.
+fn has_default_attr(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut default = false;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("fsds")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported fsds attribute, expected `default`"))
+            }
+        })?;
+    }
+    Ok(default)
+}
+
+/// Implements the conversions of a C-like enum, see
+/// [`from_and_into_for_value_derive`].
+fn enum_derive(name: &Ident, data: &DataEnum) -> TokenStream {
+    if let Some(variant) = data
+        .variants
+        .iter()
+        .find(|variant| !matches!(variant.fields, Fields::Unit))
+    {
+        return syn::Error::new_spanned(
+            variant,
+            "FromIntoValue can only be derived for enums with unit variants",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    // Matching the discriminant of every variant.
+    let variants = data.variants.iter().map(|variant| {
+        let variant = &variant.ident;
+        quote! {
+            if number == #name::#variant as i128 {
+                return Ok(#name::#variant);
+            }
+        }
+    });
+
+    let number = integer_from_value();
+    let expanded = quote! {
+        impl From<#name> for Value {
+            fn from(value: #name) -> Self {
+                Value::from(value as i64)
+            }
+        }
+
+        impl TryFrom<Value> for #name {
+            type Error = anyhow::Error;
+
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                let number = #number;
+                if let Some(number) = number {
+                    #(#variants)*
+                }
+                Err(anyhow::anyhow!("Invalid {}: {}", stringify!(#name), value))
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
 
 /// Implements `TryFrom<Value>` for a #struct and `From<#struct>` for `Value`.
 ///
@@ -21,8 +123,30 @@ use syn::{parse_macro_input, Data, DataStruct, DeriveInput, Fields, FieldsNamed}
 /// `Value::Map` to a struct.
 ///
 /// Every field of the struct must implement `TryFrom<Value>`. The struct must
-/// have the same fields as the `Value::Map` keys.
-#[proc_macro_derive(FromIntoValue)]
+/// have the same fields as the `Value::Map` keys, and every key must be a
+/// `Value::String`.
+///
+/// Integer fields (`u64`, `i32`, ...) are decoded tolerantly, see below.
+///
+/// ## Enums
+///
+/// On a C-like enum, whose variants are all unit variants, `From<#enum>` for
+/// `Value` produces a `Value::Integer` from the discriminant and
+/// `TryFrom<Value>` matches it back to the variant, failing on unknown
+/// values. The discriminant is decoded tolerantly, see below.
+///
+/// ## Integers
+///
+/// Both signed and unsigned `Value::Integer`s are accepted as long as they
+/// fit the target type, and finite `Value::F32`/`Value::F64` are rounded to
+/// the nearest integer. Some server builds send e.g. timestamps as floats.
+///
+/// ## Attributes
+///
+/// Fields annotated with `#[fsds(default)]` are set to `Default::default()`
+/// when their key is missing from the `Value::Map` instead of failing. The
+/// attribute can also be put on the struct to apply it to every field.
+#[proc_macro_derive(FromIntoValue, attributes(fsds))]
 pub fn from_and_into_for_value_derive(input: TokenStream) -> TokenStream {
     // Parsing TokenStream into DeriveInput.
     let input = parse_macro_input!(input as DeriveInput);
@@ -30,6 +154,10 @@ pub fn from_and_into_for_value_derive(input: TokenStream) -> TokenStream {
     // Extracting the struct name.
     let name = input.ident;
 
+    if let Data::Enum(data) = &input.data {
+        return enum_derive(&name, data);
+    }
+
     // Extracting the fields of the struct.
     let fields = if let Data::Struct(DataStruct {
         fields: Fields::Named(FieldsNamed { named, .. }),
@@ -42,6 +170,19 @@ pub fn from_and_into_for_value_derive(input: TokenStream) -> TokenStream {
         unimplemented!();
     };
 
+    // Fields which fall back to their default value when missing.
+    let struct_default = match has_default_attr(&input.attrs) {
+        Ok(default) => default,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let mut field_defaults = Vec::new();
+    for field in fields {
+        match has_default_attr(&field.attrs) {
+            Ok(default) => field_defaults.push(struct_default || default),
+            Err(err) => return err.to_compile_error().into(),
+        }
+    }
+
     // ----------------------- //
     // FROM<#struct> FOR VALUE //
     // ----------------------- //
@@ -73,19 +214,54 @@ pub fn from_and_into_for_value_derive(input: TokenStream) -> TokenStream {
 
     let try_from_impl = {
         // Converting the `Value::Map` fields into the struct fields.
-        let fields_def = fields.iter().map(|field| {
+        let fields_def = fields.iter().zip(&field_defaults).map(|(field, default)| {
             let field_name = &field.ident;
+            let field_type = &field.ty;
+
+            // Converting the `Value` to the field type.
+            let conversion = if is_integer_type(field_type) {
+                let number = integer_from_value();
+                quote! {
+                    {
+                        let number = #number;
+                        number
+                            .and_then(|n| <#field_type>::try_from(n).ok())
+                            .ok_or(anyhow::anyhow!(
+                                "Field {} of {} should be a {}, found {}.",
+                                stringify!(#field_name),
+                                stringify!(#name),
+                                stringify!(#field_type),
+                                value
+                            ))?
+                    }
+                }
+            } else {
+                quote! {
+                    value
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("Every field of {} should be convertible to Value.", stringify!(#name)))?
+                }
+            };
+
+            let missing = if *default {
+                quote! { Default::default() }
+            } else {
+                quote! {
+                    return Err(anyhow::anyhow!("Field {} not found in Value::Map.", stringify!(#field_name)))
+                }
+            };
+
             quote! {
-                let pos = map.iter().position(|(k, _)| k
-                    .as_str()
-                    .unwrap_or("Value::Map should contain only String keys to be converted to a struct.")
-                    == stringify!(#field_name)
-                ).ok_or(anyhow::anyhow!("Field {} not found in Value::Map.", stringify!(#field_name)))?;
-                let #field_name = map
-                    .remove(pos)
-                    .1
-                    .try_into()
-                    .map_err(|_| anyhow::anyhow!("Every field of {} should be convertible to Value.", stringify!(#name)))?;
+                let pos = map
+                    .iter()
+                    .position(|(k, _)| k.as_str() == Some(stringify!(#field_name)));
+                let #field_name = match pos {
+                    Some(pos) => {
+                        let value = map.remove(pos).1;
+                        #conversion
+                    }
+                    None => #missing,
+                };
             }
         });
 
@@ -105,6 +281,13 @@ pub fn from_and_into_for_value_derive(input: TokenStream) -> TokenStream {
                 fn try_from(value: Value) -> Result<Self, Self::Error> {
                     match value {
                         Value::Map(mut map) => {
+                            if let Some((key, _)) = map.iter().find(|(k, _)| !k.is_str()) {
+                                return Err(anyhow::anyhow!(
+                                    "Value::Map should contain only String keys to be converted to {}, found {}.",
+                                    stringify!(#name),
+                                    key
+                                ));
+                            }
                             #(#fields_def)*
                             if map.is_empty() {
                                 Ok(#name {
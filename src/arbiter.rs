@@ -0,0 +1,147 @@
+//! This module contains `ControlArbiter`, which serializes the controls sent
+//! by several tasks to the same car.
+//!
+//! Clones of `FSDSClient` can all call `set_car_controls`, in which case the
+//! car follows whichever command reaches the simulator last and the subsystems
+//! fight over it. Instead, each task can submit its commands through a
+//! `ControlSender`: once per tick the arbiter sends the most recent command,
+//! i.e. the one with the highest sequence number, and drops the others.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{client::FSDSClient, types::CarControls};
+
+/// Submits controls to a `ControlArbiter`, can be cloned and moved to other
+/// tasks.
+#[derive(Clone)]
+pub struct ControlSender {
+    latest: Arc<watch::Sender<(u64, CarControls)>>,
+    sequence: Arc<AtomicU64>,
+}
+
+impl ControlSender {
+    /// Submit `controls`, replacing any command not sent yet.
+    ///
+    /// Returns the sequence number of the command, increasing with every
+    /// submission from any sender of the same arbiter.
+    pub fn send(&self, controls: CarControls) -> u64 {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        // Keep the newest command if submissions race between the two steps.
+        self.latest.send_if_modified(|latest| {
+            if sequence > latest.0 {
+                *latest = (sequence, controls);
+                true
+            } else {
+                false
+            }
+        });
+        sequence
+    }
+}
+
+pub struct ControlArbiter {
+    sender: ControlSender,
+    stop: CancellationToken,
+}
+
+impl ControlArbiter {
+    /// Start sending the latest submitted controls to `vehicle_name` every
+    /// `tick`, using a clone of `client`.
+    ///
+    /// Nothing is sent during ticks without new submissions. The arbiter stops
+    /// when dropped.
+    pub fn spawn(client: &FSDSClient, vehicle_name: &str, tick: Duration) -> Self {
+        let (latest, mut receiver) = watch::channel((0, CarControls::default()));
+        let stop = CancellationToken::new();
+
+        let mut client = client.clone();
+        let vehicle_name = vehicle_name.to_string();
+        let task_stop = stop.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                tokio::select! {
+                    _ = task_stop.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                if receiver.has_changed().unwrap_or(false) {
+                    let (_, controls) = receiver.borrow_and_update().clone();
+                    if let Err(e) = client.set_car_controls(controls, &vehicle_name).await {
+                        tracing::warn!("Could not send controls to {}: {}", vehicle_name, e);
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender: ControlSender {
+                latest: Arc::new(latest),
+                sequence: Arc::new(AtomicU64::new(0)),
+            },
+            stop,
+        }
+    }
+
+    /// A handle to submit controls.
+    pub fn sender(&self) -> ControlSender {
+        self.sender.clone()
+    }
+}
+
+impl Drop for ControlArbiter {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use msgpack_rpc::Value;
+    use std::sync::Mutex;
+
+    #[tokio::test(start_paused = true)]
+    async fn latest_command_per_tick_wins() {
+        let throttles = Arc::new(Mutex::new(Vec::new()));
+        let recorded = throttles.clone();
+        let server = MockServer::start(move |method, params| {
+            match method {
+                "setCarControls" => {
+                    let controls = CarControls::try_from(params[0].clone()).unwrap();
+                    recorded.lock().unwrap().push(controls.throttle);
+                }
+                "isApiControlEnabled" => return (Duration::ZERO, Ok(true.into())),
+                _ => {}
+            }
+            (Duration::ZERO, Ok(Value::Nil))
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        client.enable_api_control("FSCar").await.unwrap();
+
+        let arbiter = ControlArbiter::spawn(&client, "FSCar", Duration::from_millis(50));
+        let (planner, safety) = (arbiter.sender(), arbiter.sender());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let throttle = |throttle| CarControls {
+            throttle,
+            ..Default::default()
+        };
+        assert_eq!(planner.send(throttle(1.0)), 1);
+        assert_eq!(safety.send(throttle(0.0)), 2);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.ping().await.unwrap();
+
+        assert_eq!(*throttles.lock().unwrap(), [0.0]);
+    }
+}
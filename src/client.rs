@@ -3,66 +3,786 @@
 //!
 //! The FSDSClient struct provides all the API methods available to interact
 //! with the simulator.
+//!
+//! Some data is not exposed by the FSDS server, so there is no method for it:
+//!
+//! - Energy and battery state: the car has no powertrain model, throttle is
+//!   applied as a wheel torque without any energy accounting. The consumed
+//!   energy can only be approximated from the kinematics, e.g. by integrating
+//!   the tractive power `m * a · v` over the forward acceleration `a` and
+//!   velocity `v` of `sim_get_ground_truth_kinematics`, with the car mass `m`
+//!   of the vehicle blueprint.
+//! - Lights and status indicators: the car model has no lights, and no RPC
+//!   sets an autonomous system status light (ASSI) or any other indicator.
+//! - Wind and other disturbances: the environment state only carries
+//!   gravity, air pressure, temperature and air density, and no RPC reads or
+//!   sets a wind vector. To test a controller against disturbances, inject
+//!   them on the client side, e.g. by perturbing the controls.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use msgpack_rpc::{Client, Response, Value};
+use tokio::{net::TcpStream, time::MissedTickBehavior};
+use tokio_util::{compat::TokioAsyncReadCompatExt, sync::CancellationToken};
+
+use crate::{
+    error::FsdsError,
+    guard::{GuardedStream, ResponseGuard, DEFAULT_MAX_RESPONSE_BYTES},
+    stream::{Telemetry, TelemetryConfig, TelemetryStream},
+    types::{
+        CameraInfo, CaptureConfig, CarControls, CarState, CollisionInfo, DepthImage,
+        EnvironmentState, Frame, FrameConvert, GpsData, GroundSpeedSensorData, GroundTruth,
+        GroundTruthSnapshot, ImageRequest, ImageResponse, ImageType, ImuData, KinematicsState,
+        LabeledFrame, LidarData, Pose, Quaternionr, RefereeState, SteeringConvention, Vector3r,
+    },
+};
+
+/// Future returned by `FsdsApi::call`.
+pub type ApiFuture<'a> = Pin<Box<dyn Future<Output = Result<Value, anyhow::Error>> + Send + 'a>>;
+
+/// The raw RPC interface of the simulator.
+///
+/// `FSDSClient` implements it on a live connection. Other implementations can
+/// stand in for the simulator, e.g. `record::ReplayClient` which serves a
+/// recorded session back.
+pub trait FsdsApi {
+    /// Call `method` with `params` and wait for the response.
+    fn call<'a>(&'a mut self, method: &'a str, params: &'a [Value]) -> ApiFuture<'a>;
+}
+
+impl FsdsApi for FSDSClient {
+    fn call<'a>(&'a mut self, method: &'a str, params: &'a [Value]) -> ApiFuture<'a> {
+        Box::pin(self.request(method, params))
+    }
+}
+
+/// Period of the intermediate commands sent by
+/// `FSDSClient::set_car_controls_smooth`.
+pub const SMOOTH_CONTROLS_PERIOD: Duration = Duration::from_millis(20);
+
+/// Default time `FSDSClient::init` waits for the server to answer a ping, in
+/// seconds.
+pub const DEFAULT_PING_TIMEOUT_SECS: u64 = 5;
+
+/// Number of recent requests `FSDSClient::latency_stats` is computed over.
+pub const LATENCY_SAMPLES: usize = 1000;
+
+/// Period of the collision checks enabled by
+/// `FSDSClient::enable_collision_halt`.
+pub const COLLISION_POLL_PERIOD: Duration = Duration::from_millis(50);
+
+/// Round-trip latency percentiles, see `FSDSClient::latency_stats`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Number of requests the stats are computed over.
+    pub samples: usize,
+}
+
+/// Outcome of one check of `FSDSClient::self_test`.
+#[derive(Clone, Debug)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// The error message if the check failed.
+    pub result: Result<(), String>,
+    pub duration: Duration,
+}
+
+/// Which capabilities work, see `FSDSClient::self_test`.
+#[derive(Clone, Default, Debug)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Returns true if every check succeeded.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    fn record<T>(&mut self, name: &'static str, started: Instant, result: anyhow::Result<T>) {
+        self.checks.push(SelfTestCheck {
+            name,
+            result: result.map(|_| ()).map_err(|e| e.to_string()),
+            duration: started.elapsed(),
+        });
+    }
+}
+
+/// One line per check: `ok   ping (1.2ms)` or `FAIL getImuData (...): error`.
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            match &check.result {
+                Ok(()) => writeln!(f, "ok   {} ({:?})", check.name, check.duration)?,
+                Err(e) => writeln!(f, "FAIL {} ({:?}): {}", check.name, check.duration, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How responses are converted to typed structs, see
+/// `FSDSClient::set_decode_mode`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Fail on unexpected or missing fields.
+    #[default]
+    Strict,
+    /// Drop unexpected fields and default missing ones, logging a warning for
+    /// each of them.
+    Lenient,
+}
+
+/// The async runtime whose TCP stream carries the connection, see
+/// `ClientBuilder::transport`.
+///
+/// The RPC layer and the background tasks of the client run on tokio: with
+/// `Transport::AsyncStd`, enable the `tokio1` feature of async-std, or run
+/// the client within a tokio runtime.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// `tokio::net::TcpStream`.
+    #[default]
+    Tokio,
+    /// `async_std::net::TcpStream`.
+    #[cfg(feature = "async-std")]
+    AsyncStd,
+}
+
+/// Connection options, see `FSDSClient::builder`.
+///
+/// ```ignore
+/// let client = FSDSClient::builder()
+///     .addr("192.168.1.10:41451")
+///     .tcp_nodelay(false)
+///     .connect()
+///     .await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    addr: String,
+    ping_timeout: Duration,
+    tcp_nodelay: bool,
+    max_response_bytes: u64,
+    transport: Transport,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:41451".to_string(),
+            ping_timeout: Duration::from_secs(DEFAULT_PING_TIMEOUT_SECS),
+            tcp_nodelay: true,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            transport: Transport::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Address of the simulator, `127.0.0.1:41451` by default.
+    pub fn addr(mut self, addr: &str) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
 
-use msgpack_rpc::{Client, Value};
-use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncReadCompatExt;
+    /// How long to wait for the server to answer the first ping.
+    pub fn ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping_timeout = timeout;
+        self
+    }
+
+    /// Disable Nagle's algorithm, true by default.
+    ///
+    /// Nagle's algorithm delays small writes to batch them, which adds
+    /// latency to small requests like `setCarControls`. Only disable this
+    /// option to reduce the number of packets on a slow network.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// See `FSDSClient::set_max_response_bytes`.
+    pub fn max_response_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// TCP stream implementation, `Transport::Tokio` by default.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
 
-use crate::types::{CarControls, ImageRequest, ImageType};
+    /// Connect to the simulator, see `FSDSClient::init`.
+    pub async fn connect(self) -> anyhow::Result<FSDSClient> {
+        FSDSClient::from_builder(self).await
+    }
+}
 
+/// A client connected to the simulator.
+///
+/// Cloning the client is cheap: clones share the same connection, so they can
+/// be moved to other tasks. Caches are not shared between clones.
+#[derive(Clone)]
 pub struct FSDSClient {
     client: Client,
+    /// Address the client is connected to, used by `reconnect`.
+    addr: String,
+    ping_timeout: Duration,
+    tcp_nodelay: bool,
+    transport: Transport,
+    /// Cancelled when the connection is replaced by `reconnect`.
+    connection: CancellationToken,
+    /// Last ground truth environment read for each vehicle, with its read time.
+    environment_cache: HashMap<String, (Instant, EnvironmentState)>,
+    /// Last controls sent to each vehicle.
+    last_controls: HashMap<String, CarControls>,
+    /// Vehicles whose API control was enabled by this client.
+    api_control: HashSet<String>,
+    /// Response size limit of the current connection.
+    guard: ResponseGuard,
+    decode_mode: DecodeMode,
+    output_frame: Frame,
+    steering_convention: SteeringConvention,
+    /// Round-trip time of the last `LATENCY_SAMPLES` successful requests,
+    /// shared between clones.
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+    /// Stops the collision halt task of each vehicle, shared between clones.
+    collision_halts: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    /// Set when a collision halted a vehicle, shared between clones.
+    halted: Arc<AtomicBool>,
+    /// Keep-alive period and the token stopping its task.
+    keepalive: Option<(Duration, CancellationToken)>,
+    /// Set when a keep-alive ping of the current connection failed, shared
+    /// between clones.
+    connection_lost: Arc<AtomicBool>,
 }
 
 impl FSDSClient {
-    pub async fn init(addr: Option<&str>, _timeout_value: Option<u64>) -> anyhow::Result<Self> {
-        let addr = addr.unwrap_or("127.0.0.1:41451");
+    /// Connect to the simulator, `127.0.0.1:41451` by default.
+    ///
+    /// Once the TCP connection is established, the client pings the server
+    /// and waits at most `timeout_value` seconds (5 by default) for an answer,
+    /// so that a refused connection and a server which accepts connections
+    /// but does not speak msgpack-rpc fail with distinct errors.
+    ///
+    /// Use `FSDSClient::builder` for more connection options.
+    pub async fn init(addr: Option<&str>, timeout_value: Option<u64>) -> anyhow::Result<Self> {
+        let mut builder = Self::builder();
+        if let Some(addr) = addr {
+            builder = builder.addr(addr);
+        }
+        if let Some(timeout_value) = timeout_value {
+            builder = builder.ping_timeout(Duration::from_secs(timeout_value));
+        }
+        builder.connect().await
+    }
+
+    /// Options to connect to the simulator, see `ClientBuilder`.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    async fn from_builder(builder: ClientBuilder) -> anyhow::Result<Self> {
+        let addr = builder.addr.as_str();
+        let valid = addr.rsplit_once(':').is_some_and(|(host, port)| {
+            !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0)
+        });
+        if !valid {
+            return Err(anyhow::anyhow!(
+                "Invalid simulator address {:?}, expected host:port (e.g. 127.0.0.1:41451)",
+                addr
+            ));
+        }
+
+        let guard = ResponseGuard::new(builder.max_response_bytes);
+        let client = Self::connect(
+            addr,
+            &guard,
+            builder.ping_timeout,
+            builder.tcp_nodelay,
+            builder.transport,
+        )
+        .await?;
+
+        Ok(FSDSClient {
+            client,
+            addr: addr.to_string(),
+            ping_timeout: builder.ping_timeout,
+            tcp_nodelay: builder.tcp_nodelay,
+            transport: builder.transport,
+            connection: CancellationToken::new(),
+            environment_cache: HashMap::new(),
+            last_controls: HashMap::new(),
+            api_control: HashSet::new(),
+            guard,
+            decode_mode: DecodeMode::default(),
+            output_frame: Frame::default(),
+            steering_convention: SteeringConvention::default(),
+            latencies: Arc::new(Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES))),
+            collision_halts: Arc::new(Mutex::new(HashMap::new())),
+            halted: Arc::new(AtomicBool::new(false)),
+            keepalive: None,
+            connection_lost: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Connect to a simulator running on this machine on `port`.
+    ///
+    /// Every FSDS instance listens on the `ApiServerPort` of its
+    /// `settings.json`, 41451 by default. To run several instances on one
+    /// host, give each a different port and connect with e.g.
+    /// `FSDSClient::localhost(41451 + i)`.
+    pub async fn localhost(port: u16) -> anyhow::Result<Self> {
+        Self::init(Some(&format!("127.0.0.1:{}", port)), None).await
+    }
+
+    /// Connect to the simulator and get the vehicle ready to drive.
+    ///
+    /// This is a shortcut for `init`, `ping` and `enable_api_control`. Cars
+    /// do not need to be armed (`armDisarm` only matters for multirotors), so
+    /// once this returns `set_car_controls` takes effect right away.
+    pub async fn connect_and_ready(addr: Option<&str>, vehicle_name: &str) -> anyhow::Result<Self> {
+        let mut client = Self::init(addr, None).await?;
+        client.ping().await?;
+        client.enable_api_control(vehicle_name).await?;
 
-        // Create a client with the specified timeout if needed.
-        let stream = TcpStream::connect(&addr).await?;
+        Ok(client)
+    }
+
+    /// Open a new connection to the simulator and drop the current one.
+    ///
+    /// Requests still waiting for a response on the old connection, from this
+    /// client or any of its clones, fail with a "connection replaced" error
+    /// instead of hanging. Clones made before the reconnection keep failing
+    /// the same way, clone the client again to use the new connection.
+    pub async fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        let guard = self.guard.renew();
+        let client = Self::connect(
+            &self.addr,
+            &guard,
+            self.ping_timeout,
+            self.tcp_nodelay,
+            self.transport,
+        )
+        .await?;
+
+        self.connection.cancel();
+        self.guard = guard;
+        self.client = client;
+        self.connection = CancellationToken::new();
+        self.connection_lost = Arc::new(AtomicBool::new(false));
+        if let Some((period, _)) = self.keepalive {
+            self.set_keepalive(Some(period));
+        }
+
+        Ok(())
+    }
+
+    /// Ping the simulator every `period` in the background, `None` (the
+    /// default) disables the keep-alive.
+    ///
+    /// A dead connection, e.g. dropped by a NAT timeout, is then detected
+    /// within two periods instead of on the next control command: when a
+    /// ping fails or gets no answer within `period`, `is_connected` returns
+    /// false and the keep-alive stops. The client does not reconnect on its
+    /// own, call `reconnect`, which restarts the keep-alive.
+    pub fn set_keepalive(&mut self, period: Option<Duration>) {
+        if let Some((_, stop)) = self.keepalive.take() {
+            stop.cancel();
+        }
+        let Some(period) = period else {
+            return;
+        };
+
+        let stop = CancellationToken::new();
+        self.keepalive = Some((period, stop.clone()));
+
+        let mut client = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = client.connection.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let error = match client.request_with_timeout("ping", &[], period).await {
+                    Ok(Some(_)) => continue,
+                    // The server answered, so the connection is alive.
+                    Err(e) if e.is::<FsdsError>() => continue,
+                    Ok(None) => format!("no answer within {:?}", period),
+                    Err(e) => e.to_string(),
+                };
+                tracing::warn!("Keep-alive ping to {} failed: {}", client.addr, error);
+                client.connection_lost.store(true, Ordering::Relaxed);
+                break;
+            }
+        });
+    }
+
+    /// False once a keep-alive ping failed on the current connection, see
+    /// `set_keepalive`. Always true without keep-alive.
+    pub fn is_connected(&self) -> bool {
+        !self.connection_lost.load(Ordering::Relaxed)
+    }
+
+    /// Open a connection and check that the RPC server answers a ping within
+    /// `timeout`.
+    async fn connect(
+        addr: &str,
+        guard: &ResponseGuard,
+        timeout: Duration,
+        tcp_nodelay: bool,
+        transport: Transport,
+    ) -> Result<Client, anyhow::Error> {
+        let client = Self::open(addr, guard, tcp_nodelay, transport)
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::ConnectionRefused => anyhow::anyhow!(
+                    "Connection to {} refused: is the simulator running and listening \
+                     on this port?",
+                    addr
+                ),
+                _ => anyhow::anyhow!("Could not connect to {}: {}", addr, e),
+            })?;
+
+        // Any answer, even an error, shows that the RPC layer is alive.
+        match tokio::time::timeout(timeout, client.request("ping", &[])).await {
+            Ok(Err(Value::Nil)) => Err(anyhow::anyhow!(
+                "Connected to {} over TCP but the connection was closed before the RPC server answered",
+                addr
+            )),
+            Ok(_) => Ok(client),
+            Err(_) => Err(anyhow::anyhow!(
+                "Connected to {} over TCP but the RPC server did not answer within {:?}: is another program using this port, or is the simulator still loading?",
+                addr,
+                timeout
+            )),
+        }
+    }
+
+    /// Open a TCP connection with `transport` and wrap it in an RPC client.
+    async fn open(
+        addr: &str,
+        guard: &ResponseGuard,
+        tcp_nodelay: bool,
+        transport: Transport,
+    ) -> std::io::Result<Client> {
+        match transport {
+            Transport::Tokio => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(tcp_nodelay)?;
+                Ok(Client::new(
+                    GuardedStream::new(stream, guard.clone()).compat(),
+                ))
+            }
+            #[cfg(feature = "async-std")]
+            Transport::AsyncStd => {
+                use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+                let stream = async_std::net::TcpStream::connect(addr).await?;
+                stream.set_nodelay(tcp_nodelay)?;
+                // The guard scans tokio streams, convert the stream both ways.
+                Ok(Client::new(
+                    GuardedStream::new(stream.compat(), guard.clone()).compat(),
+                ))
+            }
+        }
+    }
+
+    /// Maximum size of a single response, in bytes.
+    pub fn max_response_bytes(&self) -> u64 {
+        self.guard.max_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Set the maximum size of a single response, in bytes.
+    ///
+    /// Defaults to 64 MiB, enough for high resolution images. A response
+    /// declaring a larger size is rejected as soon as its header is read,
+    /// before it is buffered: the pending requests fail with an error naming
+    /// the limit and the connection is closed, use `reconnect` to open a new
+    /// one. The limit is shared by all clones of this client.
+    pub fn set_max_response_bytes(&self, max_bytes: u64) {
+        self.guard.max_bytes.store(max_bytes, Ordering::Relaxed);
+    }
+
+    /// Set how the typed methods (`try_get_car_state`,
+    /// `get_environment_cached`, ...) decode responses.
+    ///
+    /// In `DecodeMode::Lenient` a response which does not match the expected
+    /// schema, e.g. because the server is newer than this crate, is decoded
+    /// on a best-effort basis: unexpected fields are dropped, missing ones
+    /// take their default value and each drift is logged with
+    /// `tracing::warn!`. Values of the wrong type still fail. The default is
+    /// `DecodeMode::Strict`.
+    ///
+    /// The referee state already defaults its missing fields in both modes, so
+    /// `DecodeMode::Lenient` only drops its unexpected fields.
+    pub fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    /// Convert a response to `T` according to the decode mode.
+    ///
+    /// Use it on the raw `Value`s returned by e.g. `get_car_state`.
+    pub fn decode<T>(&self, value: Value) -> Result<T, anyhow::Error>
+    where
+        T: TryFrom<Value, Error = anyhow::Error> + Into<Value> + Default,
+    {
+        match self.decode_mode {
+            DecodeMode::Strict => T::try_from(value),
+            DecodeMode::Lenient => {
+                let name = std::any::type_name::<T>().rsplit("::").next().unwrap_or("");
+                T::try_from(lenient_merge(value, T::default().into(), name))
+            }
+        }
+    }
 
-        let client = Client::new(stream.compat());
+    /// Like `decode`, for the referee state whose points do not follow the
+    /// `Position2D` schema.
+    fn decode_referee(&self, value: Value) -> Result<RefereeState, anyhow::Error> {
+        const FIELDS: &[&str] = &["doo_counter", "laps", "initial_position", "cones"];
 
-        Ok(FSDSClient { client })
+        let value = match (self.decode_mode, value) {
+            (DecodeMode::Lenient, Value::Map(map)) => Value::Map(
+                map.into_iter()
+                    .filter(|(key, _)| {
+                        let known = key.as_str().is_some_and(|key| FIELDS.contains(&key));
+                        if !known {
+                            tracing::warn!(
+                                "unexpected field RefereeState.{} in response, ignoring it",
+                                key
+                            );
+                        }
+                        known
+                    })
+                    .collect(),
+            ),
+            (_, value) => value,
+        };
+        RefereeState::try_from(value)
+    }
+
+    /// Express the data returned by the typed getters in `frame`.
+    ///
+    /// The default is `Frame::Ned`, the frame of the simulator. With
+    /// `Frame::Enu` every `Vector3r`, `Quaternionr` and `Pose` returned by a
+    /// typed getter (kinematics, car state, collision info, GPS, GSS, camera
+    /// info, `ReadSet` readings...) is converted, see `FrameConvert`. Raw
+    /// `Value`s, controls and referee cones are left untouched.
+    pub fn set_output_frame(&mut self, frame: Frame) {
+        self.output_frame = frame;
+    }
+
+    /// Frame of the data returned by the typed getters.
+    pub fn output_frame(&self) -> Frame {
+        self.output_frame
+    }
+
+    /// Interpret `CarControls::steering` in `convention`.
+    ///
+    /// The default is `SteeringConvention::RightPositive`, the convention of
+    /// the simulator. With `SteeringConvention::LeftPositive` the steering
+    /// sign is flipped by `set_car_controls` right before sending, so
+    /// `last_car_controls` and smooth blends stay in the chosen convention.
+    pub fn set_steering_convention(&mut self, convention: SteeringConvention) {
+        self.steering_convention = convention;
+    }
+
+    /// Convention of `CarControls::steering`.
+    pub fn steering_convention(&self) -> SteeringConvention {
+        self.steering_convention
+    }
+
+    /// Like `decode`, then convert the data to the output frame.
+    pub(crate) fn decode_in_frame<T>(&self, value: Value) -> Result<T, anyhow::Error>
+    where
+        T: TryFrom<Value, Error = anyhow::Error> + Into<Value> + Default + FrameConvert,
+    {
+        Ok(self.decode::<T>(value)?.to_frame(self.output_frame))
+    }
+
+    /// Round-trip latency percentiles over the last `LATENCY_SAMPLES`
+    /// successful requests of this client and its clones.
+    ///
+    /// The latency is measured from sending the request to decoding the
+    /// response, so it includes the time spent by the simulator. Every field
+    /// is zero before the first response.
+    pub fn latency_stats(&self) -> LatencyStats {
+        let mut latencies: Vec<Duration> = self.latencies.lock().unwrap().iter().copied().collect();
+        if latencies.is_empty() {
+            return LatencyStats::default();
+        }
+        latencies.sort();
+
+        // Nearest-rank percentile.
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+            latencies[rank.clamp(1, latencies.len()) - 1]
+        };
+        LatencyStats {
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: latencies[latencies.len() - 1],
+            samples: latencies.len(),
+        }
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_SAMPLES {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    /// Send a request on the current connection and wait for the response.
+    async fn request(&self, method: &str, params: &[Value]) -> Result<Value, anyhow::Error> {
+        let sent_at = Instant::now();
+        let response = self
+            .wait_response(method, self.client.request(method, params))
+            .await?;
+        self.record_latency(sent_at.elapsed());
+        Ok(response)
+    }
+
+    /// Send all `calls` at once, then wait for their responses, in order.
+    pub(crate) async fn request_batch(
+        &self,
+        calls: &[(&str, Vec<Value>)],
+    ) -> Result<Vec<Value>, anyhow::Error> {
+        let sent_at = Instant::now();
+        let pending: Vec<_> = calls
+            .iter()
+            .map(|(method, params)| self.client.request(method, params))
+            .collect();
+
+        let mut responses = Vec::with_capacity(pending.len());
+        for ((method, _), response) in calls.iter().zip(pending) {
+            responses.push(self.wait_response(method, response).await?);
+            self.record_latency(sent_at.elapsed());
+        }
+        Ok(responses)
+    }
+
+    /// Wait for the response to a request already sent with `self.client`.
+    ///
+    /// Fails if the connection is replaced by `reconnect` in the meantime.
+    /// Errors answered by the server are returned as `FsdsError::Rpc`.
+    async fn wait_response(
+        &self,
+        method: &str,
+        response: Response,
+    ) -> Result<Value, anyhow::Error> {
+        if self.connection.is_cancelled() {
+            return Err(anyhow::anyhow!("{} failed: connection replaced", method));
+        }
+
+        tokio::select! {
+            response = response => response.map_err(|e| match (self.guard.error(), e) {
+                (Some(error), _) => anyhow::anyhow!("{} failed: {}", method, error),
+                (None, Value::Nil) => anyhow::anyhow!("{} failed: connection closed", method),
+                (None, e) => FsdsError::Rpc {
+                    method: method.to_string(),
+                    error: e.into(),
+                }
+                .into(),
+            }),
+            _ = self.connection.cancelled() => {
+                Err(anyhow::anyhow!("{} failed: connection replaced", method))
+            }
+        }
+    }
+
+    /// Send a request and wait at most `timeout` for the response.
+    ///
+    /// Returns `Ok(None)` if the response did not arrive in time. The request
+    /// itself is not retracted: the simulator may still process it.
+    async fn request_with_timeout(
+        &mut self,
+        method: &str,
+        params: &[Value],
+        timeout: Duration,
+    ) -> Result<Option<Value>, anyhow::Error> {
+        match tokio::time::timeout(timeout, self.request(method, params)).await {
+            Ok(response) => response.map(Some),
+            Err(_) => Ok(None),
+        }
     }
 
     /// Reset the vehicle to its original starting state.
     ///
     /// Note that you must call `enable_api_control` again after the call to
     /// reset.
-    pub async fn reset(&mut self) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("reset", &[])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+    ///
+    /// The state this client caches about the simulation is invalidated: API
+    /// control is marked as disabled, and the last sent controls and cached
+    /// environments are dropped.
+    pub async fn reset(&mut self) -> Result<(), anyhow::Error> {
+        self.api_control.clear();
+        self.last_controls.clear();
+        self.environment_cache.clear();
+        self.request("reset", &[]).await?;
+        Ok(())
+    }
+
+    /// Whether this client enabled API control of the vehicle, without asking
+    /// the simulator, see `is_api_control_enabled`.
+    pub fn has_api_control(&self, vehicle_name: &str) -> bool {
+        self.api_control.contains(vehicle_name)
     }
 
-    /// If connection is established then this call will return Ok(_) otherwise
+    /// If connection is established then this call will return Ok(()) otherwise
     /// it will be blocked until timeout.
-    pub async fn ping(&mut self) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("ping", &[])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+    pub async fn ping(&mut self) -> Result<(), anyhow::Error> {
+        self.request("ping", &[]).await?;
+        Ok(())
     }
 
     /// Enables API control for vehicle corresponding to vehicle_name.
-    pub async fn enable_api_control(&mut self, vehicle_name: &str) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("enableApiControl", &[true.into(), vehicle_name.into()])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+    ///
+    /// FSDS answers `enableApiControl` with nil even when control is not
+    /// granted, e.g. in competition mode, so the result is confirmed with
+    /// `isApiControlEnabled`, pipelined in the same round trip. Fails if the
+    /// server answers false to either request.
+    pub async fn enable_api_control(&mut self, vehicle_name: &str) -> Result<(), anyhow::Error> {
+        let responses = self
+            .request_batch(&[
+                ("enableApiControl", vec![true.into(), vehicle_name.into()]),
+                ("isApiControlEnabled", vec![vehicle_name.into()]),
+            ])
+            .await?;
+        if responses[0] == Value::Boolean(false) || responses[1] != Value::Boolean(true) {
+            return Err(api_control_denied(vehicle_name));
+        }
+
+        self.api_control.insert(vehicle_name.to_string());
+        Ok(())
     }
 
     /// Disable API control for vehicle corresponding to vehicle_name.
-    pub async fn disable_api_control(
-        &mut self,
-        vehicle_name: &str,
-    ) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("enableApiControl", &[false.into(), vehicle_name.into()])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+    pub async fn disable_api_control(&mut self, vehicle_name: &str) -> Result<(), anyhow::Error> {
+        self.api_control.remove(vehicle_name);
+        self.request("enableApiControl", &[false.into(), vehicle_name.into()])
+            .await?;
+        Ok(())
     }
 
     /// Returns true if API control is established.
@@ -73,17 +793,57 @@ impl FSDSClient {
     pub async fn is_api_control_enabled(
         &mut self,
         vehicle_name: &str,
-    ) -> Result<Value, anyhow::Error> {
-        self.client
+    ) -> Result<bool, anyhow::Error> {
+        let value = self
             .request("isApiControlEnabled", &[vehicle_name.into()])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+            .await?;
+        value.as_bool().ok_or(anyhow::anyhow!(
+            "isApiControlEnabled should return a Boolean, found {}",
+            value
+        ))
+    }
+
+    /// Pause or resume the simulation.
+    pub async fn sim_pause(&mut self, is_paused: bool) -> Result<(), anyhow::Error> {
+        self.request("simPause", &[is_paused.into()]).await?;
+        Ok(())
+    }
+
+    /// Returns true if the simulation is paused.
+    pub async fn sim_is_paused(&mut self) -> Result<bool, anyhow::Error> {
+        let value = self.request("simIsPaused", &[]).await?;
+        value.as_bool().ok_or(anyhow::anyhow!(
+            "simIsPaused should return a Boolean, found {}",
+            value
+        ))
+    }
+
+    /// Resume the simulation for `duration` of sim time, then pause it again.
+    ///
+    /// The call returns as soon as the simulation is resumed, use
+    /// `wait_until_paused` to know when the step is over.
+    pub async fn sim_continue_for_time(&mut self, duration: Duration) -> Result<(), anyhow::Error> {
+        self.request("simContinueForTime", &[duration.as_secs_f64().into()])
+            .await?;
+        Ok(())
+    }
+
+    /// Wait until the simulation is paused, checking every `poll_interval`.
+    pub async fn wait_until_paused(
+        &mut self,
+        poll_interval: Duration,
+    ) -> Result<(), anyhow::Error> {
+        while !self.sim_is_paused().await? {
+            tokio::time::sleep(poll_interval).await;
+        }
+        Ok(())
     }
 
     /// Get a single image.
     ///
     /// Returns bytes of png format image which can be dumped into a binary file
-    /// to create .png image.
+    /// to create .png image. Use `sim_get_image_response` for raw or float
+    /// pixels.
     ///
     /// See https://microsoft.github.io/AirSim/image_apis/ for details.
     pub async fn sim_get_image(
@@ -92,17 +852,61 @@ impl FSDSClient {
         image_type: ImageType,
         vehicle_name: &str,
     ) -> Result<Value, anyhow::Error> {
-        self.client
-            .request(
-                "simGetImage",
-                &[camera_name.into(), image_type.into(), vehicle_name.into()],
-            )
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+        self.request(
+            "simGetImage",
+            &[camera_name.into(), image_type.into(), vehicle_name.into()],
+        )
+        .await
+    }
+
+    /// Get a single image and decode its PNG bytes.
+    ///
+    /// Fails if the response is not binary, e.g. empty for a camera which
+    /// does not exist, or if it is not a valid PNG.
+    #[cfg(feature = "image")]
+    pub async fn sim_get_image_decoded(
+        &mut self,
+        camera_name: &str,
+        image_type: ImageType,
+        vehicle_name: &str,
+    ) -> Result<image::DynamicImage, anyhow::Error> {
+        let value = self
+            .sim_get_image(camera_name, image_type, vehicle_name)
+            .await?;
+        let bytes = match value {
+            Value::Binary(bytes) if !bytes.is_empty() => bytes,
+            Value::Binary(_) => {
+                return Err(anyhow::anyhow!(
+                    "simGetImage returned an empty {:?} image for camera {}",
+                    image_type,
+                    camera_name
+                ))
+            }
+            value => {
+                return Err(anyhow::anyhow!(
+                    "simGetImage should return Binary PNG data, found {}",
+                    value
+                ))
+            }
+        };
+
+        image::load_from_memory_with_format(&bytes, image::ImageFormat::Png)
+            .map_err(|e| anyhow::anyhow!("Could not decode the {:?} image: {}", image_type, e))
     }
 
     /// Get multiple images.
     ///
+    /// Request every image of a frame in a single call: the simulator waits
+    /// for one rendered frame, reads back all the requested images and
+    /// answers once, while separate calls each wait for their own frame and
+    /// may capture different sim times. Each image type of a camera is still
+    /// rendered by its own capture component, so scene, depth and
+    /// segmentation cost three render passes whether they are batched or not:
+    /// batching saves round trips and frame waits, not GPU time. The cost of
+    /// each pass depends on the resolution set in `settings.json` and has not
+    /// been measured here, profile with the simulator stats (`stat gpu`) to
+    /// size a capture rate.
+    ///
     /// See https://microsoft.github.io/AirSim/image_apis/ for details and
     /// examples.
     pub async fn sim_get_images(
@@ -110,38 +914,2026 @@ impl FSDSClient {
         requests: &[ImageRequest],
         vehicle_name: &str,
     ) -> Result<Value, anyhow::Error> {
-        self.client
+        self.request(
+            "simGetImages",
+            &[
+                Value::Array(requests.iter().map(|r| r.clone().into()).collect()),
+                vehicle_name.into(),
+            ],
+        )
+        .await
+    }
+
+    /// Get multiple images and decode them.
+    ///
+    /// A batch can mix image types, e.g. scene, depth and segmentation from
+    /// the same camera: the simulator captures them in the same frame and
+    /// answers in request order. Each response is checked against its request
+    /// (image type, `pixels_as_float` and `compress`), so a response that
+    /// does not match fails instead of being mistaken for another image.
+    /// Empty responses, which the simulator returns for image types the
+    /// camera does not support, fail with an error naming the type.
+    pub async fn sim_get_image_responses(
+        &mut self,
+        requests: &[ImageRequest],
+        vehicle_name: &str,
+    ) -> Result<Vec<ImageResponse>, anyhow::Error> {
+        let value = self.sim_get_images(requests, vehicle_name).await?;
+        image_responses_from_value(value, requests)
+    }
+
+    /// Get a single image as described by `request`, e.g. uncompressed or
+    /// with float pixels, which `sim_get_image` cannot return.
+    ///
+    /// The image is fetched with `simGetImages` and checked like in
+    /// `sim_get_image_responses`: uncompressed pixels are in
+    /// `image_data_uint8` (or `image_data_float` with `pixels_as_float`),
+    /// see e.g. `ImageResponse::to_depth`.
+    pub async fn sim_get_image_response(
+        &mut self,
+        request: &ImageRequest,
+        vehicle_name: &str,
+    ) -> Result<ImageResponse, anyhow::Error> {
+        let mut responses = self
+            .sim_get_image_responses(std::slice::from_ref(request), vehicle_name)
+            .await?;
+        Ok(responses.remove(0))
+    }
+
+    /// Get the metric depth, in meters, at pixel `(x, y)` of a perspective
+    /// depth image of `camera_name`.
+    ///
+    /// The whole image is fetched, so prefer `sim_get_image_responses` and
+    /// `ImageResponse::to_depth` to read several pixels. Fails if the pixel
+    /// is outside of the image.
+    pub async fn sim_get_depth_at(
+        &mut self,
+        camera_name: &str,
+        x: u32,
+        y: u32,
+        vehicle_name: &str,
+    ) -> Result<f32, anyhow::Error> {
+        let request = ImageRequest {
+            camera_name: camera_name.to_string(),
+            image_type: ImageType::DepthPerspective,
+            pixels_as_float: true,
+            compress: false,
+        };
+        let depth = self
+            .sim_get_image_response(&request, vehicle_name)
+            .await?
+            .to_depth()?;
+
+        depth.at(x as u64, y as u64).ok_or(anyhow::anyhow!(
+            "Pixel ({}, {}) is outside of the {}x{} depth image of {}",
+            x,
+            y,
+            depth.width,
+            depth.height,
+            camera_name
+        ))
+    }
+
+    /// Get multiple images from several vehicles at once.
+    ///
+    /// All the requests are sent before waiting for any response, so the
+    /// images of the different vehicles are captured as close in time as the
+    /// simulator allows. Responses are grouped by vehicle, in the same order
+    /// as `per_vehicle`.
+    pub async fn sim_get_images_multi(
+        &mut self,
+        per_vehicle: &[(String, Vec<ImageRequest>)],
+    ) -> Result<Vec<(String, Vec<ImageResponse>)>, anyhow::Error> {
+        let sent_at = Instant::now();
+        let pending: Vec<_> = per_vehicle
+            .iter()
+            .map(|(vehicle_name, requests)| {
+                let requests = requests.iter().map(|r| r.clone().into()).collect();
+                self.client.request(
+                    "simGetImages",
+                    &[Value::Array(requests), vehicle_name.as_str().into()],
+                )
+            })
+            .collect();
+
+        let mut responses = Vec::with_capacity(pending.len());
+        for ((vehicle_name, requests), response) in per_vehicle.iter().zip(pending) {
+            let images = self
+                .wait_response("simGetImages", response)
+                .await
+                .inspect(|_| self.record_latency(sent_at.elapsed()))
+                .and_then(|value| image_responses_from_value(value, requests))
+                .map_err(|e| {
+                    anyhow::anyhow!("simGetImages failed for vehicle {}: {}", vehicle_name, e)
+                })?;
+            responses.push((vehicle_name.clone(), images));
+        }
+
+        Ok(responses)
+    }
+
+    /// Get the pose, field of view and projection matrix of a camera.
+    pub async fn sim_get_camera_info(
+        &mut self,
+        camera_name: &str,
+        vehicle_name: &str,
+    ) -> Result<CameraInfo, anyhow::Error> {
+        let value = self
             .request(
-                "simGetImages",
-                &[
-                    Value::Array(requests.iter().map(|r| r.clone().into()).collect()),
-                    vehicle_name.into(),
-                ],
+                "simGetCameraInfo",
+                &[camera_name.into(), vehicle_name.into()],
             )
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Move a camera to `pose`, relative to the vehicle.
+    ///
+    /// The pose is sent as is, in the NED vehicle frame whatever the output
+    /// frame.
+    pub async fn sim_set_camera_pose(
+        &mut self,
+        camera_name: &str,
+        pose: Pose,
+        vehicle_name: &str,
+    ) -> Result<Value, anyhow::Error> {
+        self.request(
+            "simSetCameraPose",
+            &[camera_name.into(), pose.into(), vehicle_name.into()],
+        )
+        .await
     }
 
     /// Get Ground truth kinematics of the vehicle.
     pub async fn sim_get_ground_truth_kinematics(
         &mut self,
         vehicle_name: &str,
+    ) -> Result<KinematicsState, anyhow::Error> {
+        let value = self
+            .sim_get_ground_truth_kinematics_raw(vehicle_name)
+            .await?;
+        self.decode_in_frame(value)
+            .map_err(|e| anyhow::anyhow!("Invalid simGetGroundTruthKinematics response: {}", e))
+    }
+
+    /// Like `sim_get_ground_truth_kinematics`, returning the untyped
+    /// response.
+    pub async fn sim_get_ground_truth_kinematics_raw(
+        &mut self,
+        vehicle_name: &str,
     ) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("simGetGroundTruthKinematics", &[vehicle_name.into()])
+        self.request("simGetGroundTruthKinematics", &[vehicle_name.into()])
             .await
-            .map_err(|e| anyhow::anyhow!(e))
     }
 
-    pub async fn set_car_controls(&mut self, controls: CarControls, vehicle_name: &str) {
-        self.client
-            .request("setCarControls", &[controls.into(), vehicle_name.into()]);
+    /// Get the ground truth kinematics of the vehicle, marked as such so that
+    /// it is not mistaken for a sensor estimate.
+    pub async fn ground_truth_kinematics(
+        &mut self,
+        vehicle_name: &str,
+    ) -> Result<GroundTruth<KinematicsState>, anyhow::Error> {
+        let state = self.sim_get_ground_truth_kinematics(vehicle_name).await?;
+        Ok(GroundTruth::new(state))
     }
 
-    pub async fn get_car_state(&mut self, vehicle_name: &str) -> Result<Value, anyhow::Error> {
-        self.client
-            .request("getCarState", &[vehicle_name.into()])
-            .await
-            .map_err(|e| anyhow::anyhow!(e))
+    /// Get ground truth environment state of the vehicle.
+    pub async fn sim_get_ground_truth_environment(
+        &mut self,
+        vehicle_name: &str,
+    ) -> Result<EnvironmentState, anyhow::Error> {
+        let value = self
+            .request("simGetGroundTruthEnvironment", &[vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Get the ground truth environment state, reusing the last read if it is
+    /// not older than `max_age`.
+    ///
+    /// The environment (gravity, air density, ...) changes rarely, so this
+    /// avoids querying the simulator every tick. Use
+    /// `sim_get_ground_truth_environment` for a fresh read, or
+    /// `clear_environment_cache` to force the next call to hit the simulator.
+    pub async fn get_environment_cached(
+        &mut self,
+        vehicle_name: &str,
+        max_age: Duration,
+    ) -> Result<EnvironmentState, anyhow::Error> {
+        if let Some((read_at, state)) = self.environment_cache.get(vehicle_name) {
+            if read_at.elapsed() <= max_age {
+                return Ok(state.clone());
+            }
+        }
+
+        let state = self.sim_get_ground_truth_environment(vehicle_name).await?;
+        self.environment_cache
+            .insert(vehicle_name.to_string(), (Instant::now(), state.clone()));
+
+        Ok(state)
+    }
+
+    /// Drop every cached environment state.
+    pub fn clear_environment_cache(&mut self) {
+        self.environment_cache.clear();
+    }
+
+    /// Read the IMU `imu_name` of the vehicle, which can be empty to use the
+    /// first one.
+    ///
+    /// The orientation is returned as sent: on the first tick it may not be
+    /// normalized yet.
+    pub async fn sim_get_imu_data(
+        &mut self,
+        imu_name: &str,
+        vehicle_name: &str,
+    ) -> Result<ImuData, anyhow::Error> {
+        let value = self
+            .request("getImuData", &[imu_name.into(), vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Read the GPS of the vehicle, `gps_name` can be empty to use the
+    /// first one.
+    pub async fn sim_get_gps_data(
+        &mut self,
+        gps_name: &str,
+        vehicle_name: &str,
+    ) -> Result<GpsData, anyhow::Error> {
+        let value = self
+            .request("getGpsData", &[gps_name.into(), vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Read the last scan of the lidar `lidar_name`, which can be empty to
+    /// use the first one.
+    ///
+    /// Until the sensor completed its first scan the point cloud is empty,
+    /// which is not an error.
+    pub async fn sim_get_lidar_data(
+        &mut self,
+        lidar_name: &str,
+        vehicle_name: &str,
+    ) -> Result<LidarData, anyhow::Error> {
+        let value = self
+            .request("getLidarData", &[lidar_name.into(), vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Read the ground speed sensor (GSS) of the vehicle.
+    ///
+    /// Unlike ground truth kinematics, the GSS is allowed in scored runs.
+    pub async fn sim_get_ground_speed_sensor_data(
+        &mut self,
+        vehicle_name: &str,
+    ) -> Result<GroundSpeedSensorData, anyhow::Error> {
+        let value = self
+            .request("getGroundSpeedSensorData", &[vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Get collision info of the vehicle.
+    ///
+    /// The info refers to the last collision, check `has_collided` and
+    /// `time_stamp` to know whether it is a new one.
+    pub async fn sim_get_collision_info(
+        &mut self,
+        vehicle_name: &str,
+    ) -> Result<CollisionInfo, anyhow::Error> {
+        let value = self
+            .request("simGetCollisionInfo", &[vehicle_name.into()])
+            .await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Get the referee state: cones hit, laps and track layout.
+    ///
+    /// Unlike most calls this takes no vehicle name: the referee is global to
+    /// the simulation. The server binds `getRefereeState` without parameters
+    /// and rejects the call if any argument, even an empty vehicle name, is
+    /// sent, so none is.
+    pub async fn get_referee_state(&mut self) -> Result<RefereeState, anyhow::Error> {
+        let value = self.request("getRefereeState", &[]).await?;
+        self.decode_referee(value)
+    }
+
+    /// Names of the vehicles in the simulation.
+    ///
+    /// This uses the `listVehicles` RPC of recent AirSim versions. Older FSDS
+    /// servers do not bind it and the vehicles are then only listed in the
+    /// `Vehicles` entry of `settings.json`, in which case this fails.
+    pub async fn list_vehicles(&mut self) -> anyhow::Result<Vec<String>> {
+        let value = self.request("listVehicles", &[]).await.map_err(|e| {
+            anyhow::anyhow!(
+                "listVehicles failed, the server may be too old to list vehicles: {}",
+                e
+            )
+        })?;
+        let Value::Array(names) = value else {
+            return Err(anyhow::anyhow!(
+                "listVehicles should return an Array, found {}",
+                value
+            ));
+        };
+        names
+            .into_iter()
+            .map(|name| {
+                String::try_from(name)
+                    .map_err(|name| anyhow::anyhow!("Invalid vehicle name: {}", name))
+            })
+            .collect()
+    }
+
+    /// Get the pose the car started from, in the referee frame.
+    ///
+    /// The simulator only reports the start as the 2D
+    /// `RefereeState::initial_position`, so the returned pose has that x/y, a
+    /// zero z and an identity orientation. Ground truth kinematics are
+    /// expressed relative to the spawn point instead, where the start pose is
+    /// always the origin: use this pose to bring them into the referee frame,
+    /// e.g. to compare the car position with the referee cones.
+    ///
+    /// The referee state is global and tracks a single car, so the vehicle
+    /// name is currently ignored.
+    pub async fn sim_get_start_pose(&mut self, _vehicle_name: &str) -> Result<Pose, anyhow::Error> {
+        let referee = self.get_referee_state().await?;
+
+        let pose = Pose::new(
+            Some(Vector3r {
+                x_val: referee.initial_position.x_val,
+                y_val: referee.initial_position.y_val,
+                z_val: 0.0,
+            }),
+            Some(Quaternionr {
+                w_val: 1.0,
+                ..Default::default()
+            }),
+        );
+        Ok(pose.to_frame(self.output_frame))
+    }
+
+    /// Capture a dataset sample: scene, depth and segmentation images from
+    /// one camera along with the vehicle kinematics and the referee cones.
+    ///
+    /// The three requests are sent together. With `CaptureConfig::pause` the
+    /// simulation is paused meanwhile (and resumed even if a request fails),
+    /// so that every part of the frame refers to the same sim time.
+    pub async fn capture_labeled_frame(
+        &mut self,
+        cfg: &CaptureConfig,
+    ) -> anyhow::Result<LabeledFrame> {
+        if cfg.pause {
+            self.sim_pause(true).await?;
+        }
+        let frame = self.capture_frame(cfg).await;
+        if cfg.pause {
+            self.sim_pause(false).await?;
+        }
+        frame
+    }
+
+    async fn capture_frame(&self, cfg: &CaptureConfig) -> anyhow::Result<LabeledFrame> {
+        let request = |image_type, pixels_as_float| ImageRequest {
+            camera_name: cfg.camera_name.clone(),
+            image_type,
+            pixels_as_float,
+            compress: false,
+        };
+        let requests = [
+            request(ImageType::Scene, false),
+            request(ImageType::DepthPerspective, true),
+            request(ImageType::Segmentation, false),
+        ];
+        let images_params = [
+            Value::Array(requests.iter().map(|r| r.clone().into()).collect()),
+            cfg.vehicle_name.as_str().into(),
+        ];
+        let vehicle_params = [cfg.vehicle_name.as_str().into()];
+
+        let (images, kinematics, referee) = tokio::try_join!(
+            self.request("simGetImages", &images_params),
+            self.request("simGetGroundTruthKinematics", &vehicle_params),
+            self.request("getRefereeState", &[]),
+        )?;
+
+        let [scene, depth, segmentation]: [ImageResponse; 3] =
+            image_responses_from_value(images, &requests)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Expected 3 image responses"))?;
+        let depth: DepthImage = depth.to_depth()?;
+        let kinematics: KinematicsState = self.decode_in_frame(kinematics)?;
+        let referee = self.decode_referee(referee)?;
+
+        Ok(LabeledFrame {
+            time_stamp: scene.time_stamp,
+            scene,
+            depth,
+            segmentation,
+            kinematics,
+            cones: referee.cones,
+        })
+    }
+
+    /// Get the kinematics, environment, collision info and referee state of
+    /// the vehicle at once.
+    ///
+    /// The four requests are sent concurrently, so that they describe
+    /// (nearly) the same sim time, and share `captured_at`. Pause the
+    /// simulation around the call for an exactly synchronized snapshot.
+    pub async fn sim_get_ground_truth_snapshot(
+        &mut self,
+        vehicle_name: &str,
+    ) -> anyhow::Result<GroundTruthSnapshot> {
+        let vehicle_params = [vehicle_name.into()];
+
+        let captured_at = Instant::now();
+        let (kinematics, environment, collision, referee) = tokio::try_join!(
+            self.request("simGetGroundTruthKinematics", &vehicle_params),
+            self.request("simGetGroundTruthEnvironment", &vehicle_params),
+            self.request("simGetCollisionInfo", &vehicle_params),
+            self.request("getRefereeState", &[]),
+        )?;
+
+        Ok(GroundTruthSnapshot {
+            captured_at,
+            kinematics: self.decode_in_frame(kinematics).map_err(|e| {
+                anyhow::anyhow!("Invalid simGetGroundTruthKinematics response: {}", e)
+            })?,
+            environment: self.decode_in_frame(environment)?,
+            collision: self.decode_in_frame(collision)?,
+            referee: self.decode_referee(referee)?,
+        })
+    }
+
+    /// Send controls to the vehicle.
+    ///
+    /// The simulator silently ignores controls sent to a vehicle without API
+    /// control. If this client did not enable it with `enable_api_control`
+    /// (or it was cleared by `reset` or `disable_api_control`), it is enabled
+    /// automatically before the controls are sent and a warning is logged. As
+    /// with `enable_api_control`, the grant is confirmed with
+    /// `isApiControlEnabled` and an error is returned if it was denied.
+    ///
+    /// Returns once the simulator acknowledged the controls, or with the
+    /// error it answered.
+    ///
+    /// Controls sent from several clones race: the car follows whichever
+    /// reaches the simulator last. Use `arbiter::ControlArbiter` to let
+    /// several tasks share a car deterministically.
+    pub async fn set_car_controls(
+        &mut self,
+        controls: CarControls,
+        vehicle_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        let sent_at = Instant::now();
+        let enable = self.api_control.insert(vehicle_name.to_string()).then(|| {
+            tracing::warn!(
+                "API control not enabled for {}, enabling it before set_car_controls",
+                vehicle_name
+            );
+            (
+                self.client
+                    .request("enableApiControl", &[true.into(), vehicle_name.into()]),
+                self.client
+                    .request("isApiControlEnabled", &[vehicle_name.into()]),
+            )
+        });
+        self.last_controls
+            .insert(vehicle_name.to_string(), controls.clone());
+        let controls = CarControls {
+            steering: self.steering_convention.to_server(controls.steering),
+            ..controls
+        };
+        let response = self.client.request(
+            "setCarControls",
+            &[controls.to_value(), vehicle_name.into()],
+        );
+
+        if let Some((enable, enabled)) = enable {
+            let granted = async {
+                let enable = self.wait_response("enableApiControl", enable).await?;
+                let enabled = self.wait_response("isApiControlEnabled", enabled).await?;
+                if enable == Value::Boolean(false) || enabled != Value::Boolean(true) {
+                    return Err(api_control_denied(vehicle_name));
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(e) = granted {
+                self.api_control.remove(vehicle_name);
+                return Err(e);
+            }
+        }
+        self.wait_response("setCarControls", response).await?;
+        self.record_latency(sent_at.elapsed());
+        Ok(())
+    }
+
+    /// Blend from the last sent controls to `target` over `duration`.
+    ///
+    /// Intermediate controls, see `CarControls::lerp`, are sent every
+    /// `SMOOTH_CONTROLS_PERIOD` and the last command is `target` itself. If
+    /// no controls were sent yet, the blend starts from the default
+    /// (released) controls. Returns once `target` has been sent.
+    pub async fn set_car_controls_smooth(
+        &mut self,
+        target: CarControls,
+        duration: Duration,
+        vehicle_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        let start = self
+            .last_car_controls(vehicle_name)
+            .cloned()
+            .unwrap_or_default();
+        let steps = (duration.as_secs_f64() / SMOOTH_CONTROLS_PERIOD.as_secs_f64())
+            .ceil()
+            .max(1.0) as u32;
+
+        let mut interval = tokio::time::interval(SMOOTH_CONTROLS_PERIOD);
+        for step in 1..=steps {
+            interval.tick().await;
+            let controls = start.lerp(&target, step as f64 / steps as f64);
+            self.set_car_controls(controls, vehicle_name).await?;
+        }
+        Ok(())
+    }
+
+    /// The last controls sent to the vehicle by this client.
+    ///
+    /// The simulator does not report the controls it applied: `getCarState`
+    /// has no throttle, handbrake or gear fields. This is the closest readback
+    /// available, it is `None` until `set_car_controls` is called.
+    pub fn last_car_controls(&self, vehicle_name: &str) -> Option<&CarControls> {
+        self.last_controls.get(vehicle_name)
+    }
+
+    /// Command the car to a safe state: no throttle, full brake, straight
+    /// wheels.
+    pub async fn emergency_stop(&mut self, vehicle_name: &str) -> Result<(), anyhow::Error> {
+        let controls = CarControls {
+            brake: 1.0,
+            ..Default::default()
+        };
+        self.set_car_controls(controls, vehicle_name).await
+    }
+
+    /// Stop the car automatically when it collides.
+    ///
+    /// When enabled, a background task polls `simGetCollisionInfo` every
+    /// `COLLISION_POLL_PERIOD`. On the first collision newer than the call,
+    /// it sends `emergency_stop` and sets the flag returned by `is_halted`,
+    /// which the control loop should check to stop sending controls. The
+    /// task then exits, enable the halt again to resume watching, which also
+    /// clears the flag.
+    ///
+    /// The task stops when disabled or when the connection is replaced by
+    /// `reconnect`.
+    pub async fn enable_collision_halt(
+        &mut self,
+        vehicle_name: &str,
+        enabled: bool,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(previous) = self.collision_halts.lock().unwrap().remove(vehicle_name) {
+            previous.cancel();
+        }
+        if !enabled {
+            return Ok(());
+        }
+
+        // Collisions before this call do not count.
+        let since = self.sim_get_collision_info(vehicle_name).await?.time_stamp;
+        self.halted.store(false, Ordering::Relaxed);
+
+        let stop = CancellationToken::new();
+        self.collision_halts
+            .lock()
+            .unwrap()
+            .insert(vehicle_name.to_string(), stop.clone());
+
+        let mut client = self.clone();
+        let vehicle_name = vehicle_name.to_string();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(COLLISION_POLL_PERIOD);
+            loop {
+                tokio::select! {
+                    _ = stop.cancelled() => break,
+                    _ = client.connection.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let Ok(info) = client.sim_get_collision_info(&vehicle_name).await else {
+                    continue;
+                };
+                if info.has_collided && info.time_stamp > since {
+                    tracing::warn!(
+                        "{} collided with {}, halting it",
+                        vehicle_name,
+                        info.object_name
+                    );
+                    if let Err(e) = client.emergency_stop(&vehicle_name).await {
+                        tracing::warn!("Could not halt {}: {}", vehicle_name, e);
+                    }
+                    client.halted.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Whether a collision halted a vehicle, see `enable_collision_halt`.
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::Relaxed)
+    }
+
+    /// Run `step` in a loop until `cancel` fires, then stop the car.
+    ///
+    /// The car is commanded to a safe state with `emergency_stop` on every
+    /// exit path, including when `step` fails, so that it does not keep the
+    /// last throttle. A typical use is cancelling the token on Ctrl-C:
+    ///
+    /// ```ignore
+    /// let cancel = CancellationToken::new();
+    /// let on_ctrl_c = cancel.clone();
+    /// tokio::spawn(async move {
+    ///     tokio::signal::ctrl_c().await.ok();
+    ///     on_ctrl_c.cancel();
+    /// });
+    ///
+    /// client
+    ///     .drive_until(cancel, "FSCar", |client| {
+    ///         Box::pin(async move {
+    ///             client.set_car_controls(CarControls::default(), "FSCar").await
+    ///         })
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn drive_until<F>(
+        &mut self,
+        cancel: CancellationToken,
+        vehicle_name: &str,
+        mut step: F,
+    ) -> Result<(), anyhow::Error>
+    where
+        F: for<'a> FnMut(
+            &'a mut FSDSClient,
+        )
+            -> Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send + 'a>>,
+    {
+        let result = loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break Ok(()),
+                result = step(self) => {
+                    if result.is_err() {
+                        break result;
+                    }
+                }
+            }
+        };
+
+        let stopped = self.emergency_stop(vehicle_name).await;
+        result.and(stopped)
+    }
+
+    /// Push the readings selected by `config` to `tx` from a background task,
+    /// see `stream::TelemetryStream`.
+    ///
+    /// This lets a synchronous main loop `recv()` telemetry without being
+    /// rewritten around async. Keep the returned stream alive as long as
+    /// telemetry is needed.
+    pub fn spawn_telemetry_stream(
+        &self,
+        tx: std::sync::mpsc::Sender<Telemetry>,
+        config: TelemetryConfig,
+    ) -> TelemetryStream {
+        TelemetryStream::spawn(self, tx, config)
+    }
+
+    /// Check every capability in turn and report which ones work, with the
+    /// time each took.
+    ///
+    /// The checks are ping, API control, ground truth kinematics, car state,
+    /// a scene image of camera `0`, then the first IMU, GPS, GSS and lidar.
+    /// Every check runs even if an earlier one fails, so the report shows the
+    /// full picture of a new setup: print it to see what is missing.
+    pub async fn self_test(&mut self, vehicle_name: &str) -> SelfTestReport {
+        let mut report = SelfTestReport::default();
+
+        let started = Instant::now();
+        let result = self.ping().await;
+        report.record("ping", started, result);
+
+        let started = Instant::now();
+        let result = self.enable_api_control(vehicle_name).await;
+        report.record("enableApiControl", started, result);
+
+        let started = Instant::now();
+        let result = self.ground_truth_kinematics(vehicle_name).await;
+        report.record("simGetGroundTruthKinematics", started, result);
+
+        let started = Instant::now();
+        let result = self.get_car_state(vehicle_name).await;
+        report.record("getCarState", started, result);
+
+        let started = Instant::now();
+        let request = ImageRequest {
+            compress: false,
+            ..Default::default()
+        };
+        let result = self.sim_get_image_responses(&[request], vehicle_name).await;
+        report.record("simGetImages", started, result);
+
+        let started = Instant::now();
+        let result = self.sim_get_imu_data("", vehicle_name).await;
+        report.record("getImuData", started, result);
+
+        let started = Instant::now();
+        let result = self.sim_get_gps_data("", vehicle_name).await;
+        report.record("getGpsData", started, result);
+
+        let started = Instant::now();
+        let result = self.sim_get_ground_speed_sensor_data(vehicle_name).await;
+        report.record("getGroundSpeedSensorData", started, result);
+
+        let started = Instant::now();
+        let result = self.sim_get_lidar_data("", vehicle_name).await;
+        report.record("getLidarData", started, result);
+
+        report
+    }
+
+    /// Get the state of the car: speed and estimated kinematics.
+    pub async fn get_car_state(&mut self, vehicle_name: &str) -> Result<CarState, anyhow::Error> {
+        let value = self.request("getCarState", &[vehicle_name.into()]).await?;
+        self.decode_in_frame(value)
+    }
+
+    /// Get the car state without stalling the caller.
+    ///
+    /// Returns `Ok(None)` if the simulator does not answer within `deadline`,
+    /// so that soft-real-time loops can skip a tick instead of blocking.
+    pub async fn try_get_car_state(
+        &mut self,
+        vehicle_name: &str,
+        deadline: Duration,
+    ) -> Result<Option<CarState>, anyhow::Error> {
+        self.request_with_timeout("getCarState", &[vehicle_name.into()], deadline)
+            .await?
+            .map(|value| self.decode_in_frame(value))
+            .transpose()
+    }
+
+    /// Estimate the simulator clock speed relative to wall time.
+    ///
+    /// FSDS does not expose an RPC to read or change the clock speed: it is
+    /// only configurable through the `ClockSpeed` entry of `settings.json`.
+    /// This method samples the car state timestamp twice, `sample` apart, and
+    /// returns the ratio between elapsed sim time and elapsed wall time (1.0
+    /// means real time, 2.0 means the sim runs twice as fast).
+    ///
+    /// Timestamps returned by the simulator are sim-time nanoseconds, so
+    /// durations computed from them already account for the clock speed.
+    pub async fn sim_estimate_clock_speed(
+        &mut self,
+        vehicle_name: &str,
+        sample: Duration,
+    ) -> Result<f64, anyhow::Error> {
+        let start_wall = Instant::now();
+        let start = self.get_car_state(vehicle_name).await?;
+        tokio::time::sleep(sample).await;
+        let end = self.get_car_state(vehicle_name).await?;
+        let elapsed_wall = start_wall.elapsed().as_nanos() as f64;
+
+        let elapsed_sim = end.timestamp.saturating_sub(start.timestamp) as f64;
+        Ok(elapsed_sim / elapsed_wall)
+    }
+}
+
+/// Reshape the `value` map to the keys of the `default` map.
+///
+/// Unexpected keys are dropped and missing ones are taken from `default`,
+/// recursively for nested maps. `path` names the value in the warnings.
+fn lenient_merge(value: Value, default: Value, path: &str) -> Value {
+    let (mut map, defaults) = match (value, default) {
+        (Value::Map(map), Value::Map(defaults)) => (map, defaults),
+        (value, _) => return value,
+    };
+
+    let mut merged = Vec::with_capacity(defaults.len());
+    for (key, default) in defaults {
+        let field_path = format!("{}.{}", path, key.as_str().unwrap_or_default());
+        match map.iter().position(|(k, _)| *k == key) {
+            Some(pos) => {
+                let value = map.remove(pos).1;
+                merged.push((key, lenient_merge(value, default, &field_path)));
+            }
+            None => {
+                tracing::warn!("missing field {} in response, using default", field_path);
+                merged.push((key, default));
+            }
+        }
+    }
+    for (key, _) in map {
+        tracing::warn!("unexpected field {}.{} in response, ignoring it", path, key);
+    }
+
+    Value::Map(merged)
+}
+
+/// Split a `simGetImages` response into one map per image.
+///
+/// AirSim-derived servers answer either with an array of response maps or
+/// with a single map of parallel arrays, one entry per image for each field.
+/// A lone response map, as sent for a single image by some servers, is
+/// accepted too.
+fn image_response_values(value: Value) -> Result<Vec<Value>, anyhow::Error> {
+    let map = match value {
+        Value::Array(responses) => return Ok(responses),
+        Value::Map(map)
+            if map
+                .iter()
+                .any(|(k, v)| k.as_str() == Some("width") && !v.is_array()) =>
+        {
+            return Ok(vec![Value::Map(map)])
+        }
+        Value::Map(map) => map,
+        value => {
+            return Err(anyhow::anyhow!(
+            "simGetImages should return an Array of image responses or a Map of Arrays, found {}",
+            value
+        ))
+        }
+    };
+
+    let mut responses: Vec<Vec<(Value, Value)>> = Vec::new();
+    for (key, values) in map {
+        let Value::Array(values) = values else {
+            return Err(anyhow::anyhow!(
+                "Field {} of the simGetImages Map should be an Array, found {}",
+                key,
+                values
+            ));
+        };
+        if responses.is_empty() {
+            responses.resize(values.len(), Vec::new());
+        } else if responses.len() != values.len() {
+            return Err(anyhow::anyhow!(
+                "Field {} of the simGetImages Map has {} values, expected {}",
+                key,
+                values.len(),
+                responses.len()
+            ));
+        }
+        for (response, value) in responses.iter_mut().zip(values) {
+            response.push((key.clone(), value));
+        }
+    }
+
+    Ok(responses.into_iter().map(Value::Map).collect())
+}
+
+/// Error returned when the server does not grant API control.
+fn api_control_denied(vehicle_name: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "API control of {} was not granted: is another client controlling it, or is the simulator \
+         in competition mode?",
+        vehicle_name
+    )
+}
+
+/// Convert the array returned by `simGetImages` to `ImageResponse`s,
+/// checking that they match `requests` one to one.
+pub(crate) fn image_responses_from_value(
+    value: Value,
+    requests: &[ImageRequest],
+) -> Result<Vec<ImageResponse>, anyhow::Error> {
+    let responses: Vec<ImageResponse> = image_response_values(value)?
+        .into_iter()
+        .map(ImageResponse::try_from)
+        .collect::<Result<_, _>>()?;
+
+    if responses.len() != requests.len() {
+        return Err(anyhow::anyhow!(
+            "Expected {} image responses, got {}",
+            requests.len(),
+            responses.len()
+        ));
+    }
+    for (i, (request, response)) in requests.iter().zip(&responses).enumerate() {
+        if request.image_type != response.image_type
+            || request.pixels_as_float != response.pixels_as_float
+            || request.compress != response.compress
+        {
+            return Err(anyhow::anyhow!(
+                "Image response {} ({:?}, pixels_as_float={}, compress={}) does not match its request ({:?}, pixels_as_float={}, compress={})",
+                i,
+                response.image_type,
+                response.pixels_as_float,
+                response.compress,
+                request.image_type,
+                request.pixels_as_float,
+                request.compress
+            ));
+        }
+        // Unsupported image types come back as empty images rather than
+        // errors, which would otherwise be decoded as zero-filled data.
+        if response.width == 0
+            || response.height == 0
+            || (response.image_data_uint8.is_empty() && response.image_data_float.is_empty())
+        {
+            return Err(anyhow::anyhow!(
+                "Image response {} is empty: the simulator does not support {:?} images on camera {:?}{}",
+                i,
+                request.image_type,
+                request.camera_name,
+                if response.message.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", response.message)
+                }
+            ));
+        }
+    }
+
+    Ok(responses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    #[tokio::test]
+    async fn try_get_car_state_returns_none_after_deadline() {
+        let server = MockServer::start(|_, _| (Duration::from_millis(200), Ok(Value::Nil))).await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let state = client
+            .try_get_car_state("FSCar", Duration::from_millis(20))
+            .await
+            .unwrap();
+
+        assert!(state.is_none());
+    }
+
+    #[tokio::test]
+    async fn get_environment_cached_reuses_fresh_reads() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let server = MockServer::start(move |method, _| {
+            if method == "simGetGroundTruthEnvironment" {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            (Duration::ZERO, Ok(EnvironmentState::default().into()))
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let max_age = Duration::from_secs(60);
+        client
+            .get_environment_cached("FSCar", max_age)
+            .await
+            .unwrap();
+        client
+            .get_environment_cached("FSCar", max_age)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        client.clear_environment_cache();
+        client
+            .get_environment_cached("FSCar", max_age)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn drive_until_stops_the_car_on_cancel() {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let recorded = methods.clone();
+        let server = MockServer::start(move |method, _| {
+            recorded.lock().unwrap().push(method.to_string());
+            match method {
+                "isApiControlEnabled" => (Duration::ZERO, Ok(true.into())),
+                _ => (Duration::ZERO, Ok(Value::Nil)),
+            }
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        client.enable_api_control("FSCar").await.unwrap();
+        methods.lock().unwrap().clear();
+
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        client
+            .drive_until(cancel, "FSCar", |client| {
+                let trigger = trigger.clone();
+                Box::pin(async move {
+                    client.ping().await?;
+                    trigger.cancel();
+                    std::future::pending().await
+                })
+            })
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+
+        assert_eq!(
+            *methods.lock().unwrap(),
+            vec!["ping", "setCarControls", "ping"]
+        );
+    }
+
+    #[tokio::test]
+    async fn denied_api_control_fails() {
+        let server = MockServer::start(|method, params| match method {
+            "isApiControlEnabled" => (
+                Duration::ZERO,
+                Ok((params[0].as_str() == Some("FSCar")).into()),
+            ),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        client.enable_api_control("FSCar").await.unwrap();
+        assert!(client.has_api_control("FSCar"));
+        assert!(client.enable_api_control("Other").await.is_err());
+        assert!(!client.has_api_control("Other"));
+    }
+
+    #[tokio::test]
+    async fn keepalive_detects_a_stalled_connection() {
+        let stalled = Arc::new(AtomicBool::new(false));
+        let server_stalled = stalled.clone();
+        let server = MockServer::start(move |_, _| {
+            let delay = match server_stalled.load(Ordering::SeqCst) {
+                true => Duration::from_secs(5),
+                false => Duration::ZERO,
+            };
+            (delay, Ok(Value::Nil))
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        client.set_keepalive(Some(Duration::from_millis(20)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(client.is_connected());
+
+        stalled.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!client.is_connected());
+
+        stalled.store(false, Ordering::SeqCst);
+        client.reconnect().await.unwrap();
+        assert!(client.is_connected());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(client.is_connected());
+        client.set_keepalive(None);
+    }
+
+    #[tokio::test]
+    async fn reconnect_fails_pending_requests() {
+        let server = MockServer::start(|method, _| match method {
+            "slow" => (Duration::from_secs(5), Ok(Value::Nil)),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let shared = client.clone();
+        let pending = tokio::spawn(async move { shared.request("slow", &[]).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.reconnect().await.unwrap();
+
+        let error = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .expect("pending request should not hang")
+            .unwrap()
+            .unwrap_err();
+        assert!(error.to_string().contains("connection replaced"));
+        client.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_are_rejected() {
+        let server = MockServer::start(|method, _| match method {
+            "large" => (Duration::ZERO, Ok(vec![0u8; 4096].into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        assert_eq!(client.max_response_bytes(), DEFAULT_MAX_RESPONSE_BYTES);
+
+        client.request("large", &[]).await.unwrap();
+        client.set_max_response_bytes(1024);
+        let error = client.request("large", &[]).await.unwrap_err();
+        assert!(error.to_string().contains("max_response_bytes"));
+    }
+
+    #[tokio::test]
+    async fn lenient_decode_fills_schema_drift() {
+        let server = MockServer::start(|_, _| (Duration::ZERO, Ok(Value::Nil))).await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        // A newer server renaming `timestamp` and adding a field.
+        let mut value: Value = CarState {
+            speed: 3.0,
+            ..Default::default()
+        }
+        .into();
+        if let Value::Map(map) = &mut value {
+            map.retain(|(k, _)| k.as_str() != Some("timestamp"));
+            map.push(("time_stamp".into(), 42.into()));
+        }
+
+        assert!(client.decode::<CarState>(value.clone()).is_err());
+        client.set_decode_mode(DecodeMode::Lenient);
+        let state: CarState = client.decode(value).unwrap();
+        assert_eq!(state.speed, 3.0);
+        assert_eq!(state.timestamp, 0);
+    }
+
+    #[tokio::test]
+    async fn set_car_controls_waits_for_the_simulator() {
+        let throttles = Arc::new(Mutex::new(Vec::new()));
+        let recorded = throttles.clone();
+        let server = MockServer::start(move |method, params| match method {
+            "setCarControls" if params[1] == Value::from("FSCar") => {
+                let controls = CarControls::try_from(params[0].clone()).unwrap();
+                recorded.lock().unwrap().push(controls.throttle);
+                (Duration::from_millis(20), Ok(Value::Nil))
+            }
+            "setCarControls" => (Duration::ZERO, Err("unknown vehicle".into())),
+            "isApiControlEnabled" => (Duration::ZERO, Ok(true.into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let controls = CarControls {
+            throttle: 0.5,
+            ..Default::default()
+        };
+        client
+            .set_car_controls(controls.clone(), "FSCar")
+            .await
+            .unwrap();
+        // Received before returning, without any other request to flush it.
+        assert_eq!(*throttles.lock().unwrap(), [0.5]);
+
+        assert!(client.set_car_controls(controls, "Other").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn set_car_controls_enables_api_control() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let server_calls = calls.clone();
+        let server = MockServer::start(move |method, _| {
+            server_calls.lock().unwrap().push(method.to_string());
+            match method {
+                "isApiControlEnabled" => (Duration::ZERO, Ok(true.into())),
+                _ => (Duration::ZERO, Ok(Value::Nil)),
+            }
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        calls.lock().unwrap().clear();
+
+        client
+            .set_car_controls(CarControls::default(), "FSCar")
+            .await
+            .unwrap();
+        client
+            .set_car_controls(CarControls::default(), "FSCar")
+            .await
+            .unwrap();
+        client.reset().await.unwrap();
+        client
+            .set_car_controls(CarControls::default(), "FSCar")
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "enableApiControl",
+                "isApiControlEnabled",
+                "setCarControls",
+                "setCarControls",
+                "reset",
+                "enableApiControl",
+                "isApiControlEnabled",
+                "setCarControls",
+                "ping"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn set_car_controls_fails_when_api_control_is_denied() {
+        // FSDS answers nil to enableApiControl even when it denies control.
+        let server = MockServer::start(|method, _| match method {
+            "isApiControlEnabled" => (Duration::ZERO, Ok(false.into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let error = client
+            .set_car_controls(CarControls::default(), "FSCar")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("not granted"));
+        assert!(!client.has_api_control("FSCar"));
+    }
+
+    #[tokio::test]
+    async fn ground_speed_sensor_is_decoded() {
+        let server = MockServer::start(|method, params| match method {
+            "getGroundSpeedSensorData" if params == [Value::from("FSCar")] => (
+                Duration::ZERO,
+                Ok(GroundSpeedSensorData {
+                    time_stamp: 7,
+                    linear_velocity: Vector3r {
+                        x_val: 3.0,
+                        y_val: 4.0,
+                        ..Default::default()
+                    },
+                    angular_velocity: Vector3r {
+                        z_val: 0.5,
+                        ..Default::default()
+                    },
+                }
+                .into()),
+            ),
+            _ => (Duration::ZERO, Err(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let data = client
+            .sim_get_ground_speed_sensor_data("FSCar")
+            .await
+            .unwrap();
+        assert_eq!(data.time_stamp, 7);
+        assert_eq!(data.linear_velocity.x_val, 3.0);
+        assert_eq!(data.angular_velocity.z_val, 0.5);
+        assert_eq!(data.speed(), 5.0);
+    }
+
+    /// Answer `simGetImages` with one response per request, in `order`.
+    fn image_server(
+        order: fn(usize) -> usize,
+    ) -> impl Fn(&str, &[Value]) -> (Duration, Result<Value, Value>) {
+        move |_, params| {
+            let Some(Value::Array(requests)) = params.first().cloned() else {
+                return (Duration::ZERO, Ok(Value::Nil));
+            };
+            let requests: Vec<ImageRequest> = requests
+                .into_iter()
+                .map(|r| r.try_into().unwrap())
+                .collect();
+            let responses = (0..requests.len())
+                .map(|i| {
+                    let request = &requests[order(i)];
+                    ImageResponse {
+                        image_type: request.image_type,
+                        pixels_as_float: request.pixels_as_float,
+                        compress: request.compress,
+                        width: 1,
+                        height: 1,
+                        image_data_uint8: vec![0; 3],
+                        ..Default::default()
+                    }
+                    .into()
+                })
+                .collect();
+            (Duration::ZERO, Ok(Value::Array(responses)))
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_image_batch_matches_requests() {
+        let requests = [
+            ImageRequest::default(),
+            ImageRequest {
+                image_type: ImageType::DepthVis,
+                pixels_as_float: true,
+                compress: false,
+                ..Default::default()
+            },
+            ImageRequest {
+                image_type: ImageType::Segmentation,
+                ..Default::default()
+            },
+        ];
+
+        let server = MockServer::start(image_server(|i| i)).await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let responses = client
+            .sim_get_image_responses(&requests, "FSCar")
+            .await
+            .unwrap();
+        let types: Vec<_> = responses.iter().map(|r| r.image_type).collect();
+        assert_eq!(
+            types,
+            [
+                ImageType::Scene,
+                ImageType::DepthVis,
+                ImageType::Segmentation
+            ]
+        );
+        assert!(responses[1].pixels_as_float && !responses[1].compress);
+
+        let server = MockServer::start(image_server(|i| 2 - i)).await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        assert!(client
+            .sim_get_image_responses(&requests, "FSCar")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn smooth_controls_ramp_to_target() {
+        let throttles = Arc::new(Mutex::new(Vec::new()));
+        let recorded = throttles.clone();
+        let server = MockServer::start(move |method, params| {
+            match method {
+                "setCarControls" => {
+                    let controls = CarControls::try_from(params[0].clone()).unwrap();
+                    recorded.lock().unwrap().push(controls.throttle);
+                }
+                "isApiControlEnabled" => return (Duration::ZERO, Ok(true.into())),
+                _ => {}
+            }
+            (Duration::ZERO, Ok(Value::Nil))
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        client.enable_api_control("FSCar").await.unwrap();
+
+        let target = CarControls {
+            throttle: 1.0,
+            ..Default::default()
+        };
+        client
+            .set_car_controls_smooth(target, SMOOTH_CONTROLS_PERIOD * 4, "FSCar")
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+
+        assert_eq!(*throttles.lock().unwrap(), [0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(client.last_car_controls("FSCar").unwrap().throttle, 1.0);
+    }
+
+    #[tokio::test]
+    async fn referee_state_is_requested_without_arguments() {
+        let server = MockServer::start(|method, params| match (method, params) {
+            ("getRefereeState", []) => (Duration::ZERO, Ok(Value::Map(vec![]))),
+            _ => (Duration::ZERO, Err("wrong arguments".into())),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let state = client.get_referee_state().await.unwrap();
+        assert_eq!(state.doo_counter, 0);
+    }
+
+    #[tokio::test]
+    async fn lenient_mode_drops_unexpected_referee_fields() {
+        let server = MockServer::start(|method, _| match method {
+            "getRefereeState" => (
+                Duration::ZERO,
+                Ok(Value::Map(vec![
+                    ("doo_counter".into(), 3.into()),
+                    ("penalty_seconds".into(), 2.into()),
+                ])),
+            ),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        assert!(client.get_referee_state().await.is_err());
+        client.set_decode_mode(DecodeMode::Lenient);
+        assert_eq!(client.get_referee_state().await.unwrap().doo_counter, 3);
+    }
+
+    #[tokio::test]
+    async fn malformed_addresses_are_rejected() {
+        for addr in ["localhost", "127.0.0.1:", ":41451", "127.0.0.1:99999"] {
+            let error = FSDSClient::init(Some(addr), None).await.err().unwrap();
+            assert!(error.to_string().contains("Invalid simulator address"));
+        }
+
+        let server = MockServer::start(|_, _| (Duration::ZERO, Ok(Value::Nil))).await;
+        let port = server.addr().rsplit_once(':').unwrap().1.parse().unwrap();
+        FSDSClient::localhost(port)
+            .await
+            .unwrap()
+            .ping()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn latency_stats_track_slow_requests() {
+        let server = MockServer::start(|method, _| match method {
+            "slow" => (Duration::from_millis(100), Ok(Value::Nil)),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        assert_eq!(client.latency_stats(), LatencyStats::default());
+
+        for _ in 0..99 {
+            client.ping().await.unwrap();
+        }
+        client.request("slow", &[]).await.unwrap();
+
+        let stats = client.latency_stats();
+        assert_eq!(stats.samples, 100);
+        assert!(stats.p50 < Duration::from_millis(50));
+        assert!(stats.p99 < Duration::from_millis(50));
+        assert!(stats.max >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn lidar_data_is_typed() {
+        let scans = Arc::new(AtomicUsize::new(0));
+        let server_scans = scans.clone();
+        let server = MockServer::start(move |method, _| match method {
+            "getLidarData" => {
+                // The first scan is not ready yet.
+                let point_cloud = match server_scans.fetch_add(1, Ordering::SeqCst) {
+                    0 => vec![],
+                    _ => vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0],
+                };
+                let data = LidarData {
+                    point_cloud,
+                    time_stamp: 7,
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(data.into()))
+            }
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let empty = client.sim_get_lidar_data("", "FSCar").await.unwrap();
+        assert!(empty.points().is_empty());
+        let scan = client.sim_get_lidar_data("", "FSCar").await.unwrap();
+        assert_eq!(scan.time_stamp, 7);
+        assert_eq!(scan.points().len(), 2);
+        assert_eq!(scan.points()[1].x_val, 4.0);
+    }
+
+    #[cfg(feature = "image")]
+    #[tokio::test]
+    async fn png_images_are_decoded() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(2, 3, image::Rgb([10, 20, 30]))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+        let server = MockServer::start(move |method, params| match method {
+            "simGetImage" if params[0] == Value::from("0") => {
+                (Duration::ZERO, Ok(Value::Binary(png.clone())))
+            }
+            "simGetImage" => (Duration::ZERO, Ok(Value::Binary(vec![]))),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let image = client
+            .sim_get_image_decoded("0", ImageType::Scene, "FSCar")
+            .await
+            .unwrap();
+        assert_eq!((image.width(), image.height()), (2, 3));
+        assert_eq!(image.to_rgb8().get_pixel(1, 2).0, [10, 20, 30]);
+
+        let error = client
+            .sim_get_image_decoded("missing", ImageType::Scene, "FSCar")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("missing"));
+    }
+
+    #[tokio::test]
+    async fn single_image_can_be_uncompressed() {
+        let server = MockServer::start(image_server(|i| i)).await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let request = ImageRequest {
+            camera_name: "cam".to_string(),
+            image_type: ImageType::Segmentation,
+            ..Default::default()
+        };
+        let image = client
+            .sim_get_image_response(&request, "FSCar")
+            .await
+            .unwrap();
+        assert_eq!(image.image_type, ImageType::Segmentation);
+        assert!(!image.compress);
+        assert_eq!(image.channels().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn depth_at_reads_one_pixel() {
+        let server = MockServer::start(|method, _| match method {
+            "simGetImages" => {
+                let depth = ImageResponse {
+                    image_type: ImageType::DepthPerspective,
+                    pixels_as_float: true,
+                    compress: false,
+                    width: 2,
+                    height: 2,
+                    image_data_float: vec![1.0, 2.0, 3.0, 4.0],
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(Value::Array(vec![depth.into()])))
+            }
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let depth = client.sim_get_depth_at("cam", 0, 1, "FSCar").await.unwrap();
+        assert_eq!(depth, 3.0);
+        assert!(client.sim_get_depth_at("cam", 2, 0, "FSCar").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn labeled_frame_bundles_images_and_labels() {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let recorded = methods.clone();
+        let images = image_server(|i| i);
+        let server = MockServer::start(move |method, params| {
+            recorded.lock().unwrap().push(method.to_string());
+            match method {
+                "simGetImages" => {
+                    let (delay, response) = images(method, params);
+                    // Give the images a size matching their data.
+                    let Ok(Value::Array(responses)) = response else {
+                        return (delay, response);
+                    };
+                    let responses = responses
+                        .into_iter()
+                        .map(|r| {
+                            let mut r = ImageResponse::try_from(r).unwrap();
+                            (r.width, r.height, r.time_stamp) = (1, 1, 42);
+                            r.image_data_uint8 = vec![0; 3];
+                            r.image_data_float = vec![7.0];
+                            r.into()
+                        })
+                        .collect();
+                    (delay, Ok(Value::Array(responses)))
+                }
+                "simGetGroundTruthKinematics" => {
+                    (Duration::ZERO, Ok(KinematicsState::default().into()))
+                }
+                "getRefereeState" => (Duration::ZERO, Ok(Value::Map(vec![]))),
+                _ => (Duration::ZERO, Ok(Value::Nil)),
+            }
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        methods.lock().unwrap().clear();
+
+        let frame = client
+            .capture_labeled_frame(&CaptureConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(frame.time_stamp, 42);
+        assert_eq!(frame.depth.at(0, 0), Some(7.0));
+        assert_eq!(frame.segmentation.image_type, ImageType::Segmentation);
+
+        let methods = methods.lock().unwrap();
+        assert_eq!(methods.first().map(String::as_str), Some("simPause"));
+        assert_eq!(methods.last().map(String::as_str), Some("simPause"));
+    }
+
+    #[tokio::test]
+    async fn ground_truth_snapshot_sends_requests_concurrently() {
+        let server = MockServer::start(|method, _| {
+            let delay = Duration::from_millis(100);
+            match method {
+                "simGetGroundTruthKinematics" => (delay, Ok(KinematicsState::default().into())),
+                "simGetGroundTruthEnvironment" => (delay, Ok(EnvironmentState::default().into())),
+                "simGetCollisionInfo" => (delay, Ok(CollisionInfo::default().into())),
+                "getRefereeState" => (delay, Ok(Value::Map(vec![]))),
+                _ => (Duration::ZERO, Ok(Value::Nil)),
+            }
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let started = Instant::now();
+        let snapshot = client.sim_get_ground_truth_snapshot("FSCar").await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert!(snapshot.captured_at >= started);
+        assert!(!snapshot.collision.has_collided);
+    }
+
+    #[tokio::test]
+    async fn left_positive_steering_is_flipped_on_the_wire() {
+        let steerings = Arc::new(Mutex::new(Vec::new()));
+        let recorded = steerings.clone();
+        let server = MockServer::start(move |method, params| {
+            match method {
+                "setCarControls" => {
+                    let controls = CarControls::try_from(params[0].clone()).unwrap();
+                    recorded.lock().unwrap().push(controls.steering);
+                }
+                "isApiControlEnabled" => return (Duration::ZERO, Ok(true.into())),
+                _ => {}
+            }
+            (Duration::ZERO, Ok(Value::Nil))
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let left = CarControls {
+            steering: 0.5,
+            ..Default::default()
+        };
+
+        client
+            .set_car_controls(left.clone(), "FSCar")
+            .await
+            .unwrap();
+        client.set_steering_convention(SteeringConvention::LeftPositive);
+        client
+            .set_car_controls(left.clone(), "FSCar")
+            .await
+            .unwrap();
+
+        assert_eq!(*steerings.lock().unwrap(), [0.5, -0.5]);
+        assert_eq!(client.last_car_controls("FSCar"), Some(&left));
+    }
+
+    #[tokio::test]
+    async fn camera_pose_is_set_and_read_back() {
+        let pose = Arc::new(Mutex::new(Value::from(Pose::default())));
+        let stored = pose.clone();
+        let server = MockServer::start(move |method, params| match method {
+            "simSetCameraPose" => {
+                *stored.lock().unwrap() = params[1].clone();
+                (Duration::ZERO, Ok(Value::Nil))
+            }
+            "simGetCameraInfo" => {
+                let info = CameraInfo {
+                    pose: Pose::try_from(stored.lock().unwrap().clone()).unwrap(),
+                    fov: 90.0,
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(info.into()))
+            }
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let position = Vector3r {
+            x_val: 1.0,
+            y_val: 0.0,
+            z_val: -0.5,
+        };
+        let pose = Pose::new(Some(position), Some(Quaternionr::default()));
+        client
+            .sim_set_camera_pose("cam", pose, "FSCar")
+            .await
+            .unwrap();
+
+        let info = client.sim_get_camera_info("cam", "FSCar").await.unwrap();
+        assert_eq!(info.pose.position.x_val, 1.0);
+        assert_eq!(info.pose.position.z_val, -0.5);
+        assert_eq!(info.fov, 90.0);
+    }
+
+    #[tokio::test]
+    async fn refused_and_unresponsive_servers_are_told_apart() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        // Accepts connections but never answers.
+        let silent = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let error = FSDSClient::init(Some(&addr), Some(1)).await.err().unwrap();
+        assert!(error.to_string().contains("did not answer"));
+
+        silent.abort();
+        let _ = silent.await;
+        let error = FSDSClient::init(Some(&addr), Some(1)).await.err().unwrap();
+        assert!(error.to_string().contains("refused"));
+    }
+
+    #[tokio::test]
+    async fn collision_halts_the_car() {
+        let controls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = controls.clone();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let server_polls = polls.clone();
+        let server = MockServer::start(move |method, params| match method {
+            "simGetCollisionInfo" => {
+                // An old collision, then a new one from the third poll.
+                let time_stamp = if server_polls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    10
+                } else {
+                    20
+                };
+                let info = CollisionInfo {
+                    has_collided: true,
+                    time_stamp,
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(info.into()))
+            }
+            "setCarControls" => {
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(CarControls::try_from(params[0].clone()).unwrap());
+                (Duration::ZERO, Ok(Value::Nil))
+            }
+            "isApiControlEnabled" => (Duration::ZERO, Ok(true.into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        client.enable_api_control("FSCar").await.unwrap();
+
+        client.enable_collision_halt("FSCar", true).await.unwrap();
+        tokio::time::sleep(COLLISION_POLL_PERIOD / 2).await;
+        assert!(!client.is_halted());
+
+        tokio::time::sleep(COLLISION_POLL_PERIOD * 3).await;
+        assert!(client.is_halted());
+        assert_eq!(controls.lock().unwrap().len(), 1);
+        assert_eq!(controls.lock().unwrap()[0].brake, 1.0);
+    }
+
+    #[tokio::test]
+    async fn empty_image_names_the_unsupported_type() {
+        let server = MockServer::start(|method, _| match method {
+            "simGetImages" => {
+                let response = ImageResponse {
+                    image_type: ImageType::Infrared,
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(Value::Array(vec![response.into()])))
+            }
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let request = ImageRequest {
+            image_type: ImageType::Infrared,
+            ..Default::default()
+        };
+        let error = client
+            .sim_get_image_responses(&[request], "FSCar")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Infrared"));
+    }
+
+    #[test]
+    fn map_of_arrays_image_responses_are_split() {
+        let requests = [ImageRequest::default(), ImageRequest::default()];
+        let response = |time_stamp| ImageResponse {
+            time_stamp,
+            compress: false,
+            width: 1,
+            height: 1,
+            image_data_uint8: vec![0; 3],
+            ..Default::default()
+        };
+        let Value::Map(first) = Value::from(response(1)) else {
+            unreachable!()
+        };
+        let Value::Map(second) = Value::from(response(2)) else {
+            unreachable!()
+        };
+        let parallel = first
+            .into_iter()
+            .zip(second)
+            .map(|((key, a), (_, b))| (key, Value::Array(vec![a, b])))
+            .collect();
+
+        let responses = image_responses_from_value(Value::Map(parallel), &requests).unwrap();
+        assert_eq!(responses[0].time_stamp, 1);
+        assert_eq!(responses[1].time_stamp, 2);
+
+        let single = image_responses_from_value(response(3).into(), &requests[..1]).unwrap();
+        assert_eq!(single[0].time_stamp, 3);
+
+        assert!(image_responses_from_value(Value::Nil, &requests).is_err());
+    }
+
+    #[tokio::test]
+    async fn enu_output_frame_converts_kinematics() {
+        let server = MockServer::start(|method, _| match method {
+            "simGetGroundTruthKinematics" => {
+                let kinematics = KinematicsState {
+                    position: Vector3r {
+                        x_val: 1.0,
+                        y_val: 2.0,
+                        z_val: 3.0,
+                    },
+                    ..Default::default()
+                };
+                (Duration::ZERO, Ok(kinematics.into()))
+            }
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let ned = client.ground_truth_kinematics("FSCar").await.unwrap();
+        assert_eq!(ned.position.x_val, 1.0);
+
+        client.set_output_frame(Frame::Enu);
+        let enu = client.ground_truth_kinematics("FSCar").await.unwrap();
+        assert_eq!(
+            (enu.position.x_val, enu.position.y_val, enu.position.z_val),
+            (2.0, 1.0, -3.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn ground_truth_kinematics_is_typed() {
+        let server = MockServer::start(|method, params| match method {
+            "simGetGroundTruthKinematics" if params[0] == Value::from("FSCar") => {
+                (Duration::ZERO, Ok(KinematicsState::default().into()))
+            }
+            "simGetGroundTruthKinematics" => (
+                Duration::ZERO,
+                Ok(Value::Map(vec![("position".into(), "nowhere".into())])),
+            ),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let state = client
+            .sim_get_ground_truth_kinematics("FSCar")
+            .await
+            .unwrap();
+        assert_eq!(state.position.x_val, 0.0);
+        let raw = client
+            .sim_get_ground_truth_kinematics_raw("FSCar")
+            .await
+            .unwrap();
+        assert!(matches!(raw, Value::Map(_)));
+
+        let error = client
+            .sim_get_ground_truth_kinematics("Other")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("simGetGroundTruthKinematics"));
+    }
+
+    #[tokio::test]
+    async fn vehicles_are_listed() {
+        let server = MockServer::start(|method, _| match method {
+            "listVehicles" => (
+                Duration::ZERO,
+                Ok(Value::Array(vec!["FSCar".into(), "FSCar2".into()])),
+            ),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        assert_eq!(client.list_vehicles().await.unwrap(), ["FSCar", "FSCar2"]);
+    }
+
+    #[tokio::test]
+    async fn self_test_reports_every_check() {
+        let server = MockServer::start(|method, _| match method {
+            "getImuData" => (Duration::ZERO, Ok(ImuData::default().into())),
+            "getLidarData" => (Duration::ZERO, Err("no lidar".into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let report = client.self_test("FSCar").await;
+        assert_eq!(report.checks.len(), 9);
+        assert!(!report.passed());
+
+        let result = |name| {
+            report
+                .checks
+                .iter()
+                .find(|check| check.name == name)
+                .unwrap()
+                .result
+                .clone()
+        };
+        assert!(result("ping").is_ok());
+        assert!(result("getImuData").is_ok());
+        assert!(result("getLidarData").unwrap_err().contains("no lidar"));
+        assert!(report.to_string().contains("FAIL getLidarData"));
+    }
+
+    #[tokio::test]
+    async fn server_errors_can_be_downcast() {
+        let server = MockServer::start(|method, _| match method {
+            "getCarState" => (Duration::ZERO, Err("unknown vehicle".into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let error = client.get_car_state("Nope").await.unwrap_err();
+        let Some(FsdsError::Rpc { method, error }) = error.downcast_ref::<FsdsError>() else {
+            panic!("expected an RPC error, got {}", error);
+        };
+        assert_eq!(method, "getCarState");
+        assert_eq!(error.message, "unknown vehicle");
+    }
+
+    #[tokio::test]
+    async fn builder_applies_options() {
+        let server = MockServer::start(|_, _| (Duration::ZERO, Ok(Value::Nil))).await;
+
+        let client = FSDSClient::builder()
+            .addr(&server.addr())
+            .ping_timeout(Duration::from_secs(2))
+            .tcp_nodelay(false)
+            .max_response_bytes(1024)
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(client.ping_timeout, Duration::from_secs(2));
+        assert!(!client.tcp_nodelay);
+        assert_eq!(client.max_response_bytes(), 1024);
+    }
+
+    #[cfg(feature = "async-std")]
+    #[tokio::test]
+    async fn async_std_transport_connects() {
+        let server = MockServer::start(|method, _| match method {
+            "getCarState" => (Duration::ZERO, Ok(CarState::default().into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+
+        let mut client = FSDSClient::builder()
+            .addr(&server.addr())
+            .transport(Transport::AsyncStd)
+            .connect()
+            .await
+            .unwrap();
+        client.get_car_state("FSCar").await.unwrap();
+        client.reconnect().await.unwrap();
+        client.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reset_invalidates_cached_state() {
+        let server = MockServer::start(|method, _| match method {
+            "simGetGroundTruthEnvironment" => {
+                (Duration::ZERO, Ok(EnvironmentState::default().into()))
+            }
+            "isApiControlEnabled" => (Duration::ZERO, Ok(true.into())),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        client.enable_api_control("FSCar").await.unwrap();
+        client
+            .set_car_controls(CarControls::default(), "FSCar")
+            .await
+            .unwrap();
+        client
+            .get_environment_cached("FSCar", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(client.has_api_control("FSCar"));
+
+        client.reset().await.unwrap();
+
+        assert!(!client.has_api_control("FSCar"));
+        assert!(client.last_car_controls("FSCar").is_none());
+        assert!(client.environment_cache.is_empty());
     }
 }
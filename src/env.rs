@@ -0,0 +1,169 @@
+//! This module contains `CarEnv`, a gym-style wrapper around `FSDSClient`
+//! meant for reinforcement learning.
+//!
+//! Actions are the normalized vectors described in
+//! `CarControls::from_action`. Each `step` applies the action, lets the
+//! simulator run for `step_duration` and returns the new observation, a
+//! reward and whether the episode is over.
+
+use std::time::Duration;
+
+use crate::{
+    client::FSDSClient,
+    types::{CarControls, CarState, CollisionInfo, RefereeState},
+};
+
+/// Everything the agent observes after a reset or a step.
+#[derive(Debug)]
+pub struct Observation {
+    pub car_state: CarState,
+    pub collision: CollisionInfo,
+    pub referee: RefereeState,
+}
+
+pub struct CarEnv {
+    client: FSDSClient,
+    vehicle_name: String,
+    step_duration: Duration,
+    /// `doo_counter` at the beginning of the episode.
+    initial_doo_counter: u64,
+    /// Collision time stamp at the beginning of the episode.
+    initial_collision_time_stamp: u64,
+}
+
+impl CarEnv {
+    pub fn new(client: FSDSClient, vehicle_name: &str, step_duration: Duration) -> Self {
+        Self {
+            client,
+            vehicle_name: vehicle_name.to_string(),
+            step_duration,
+            initial_doo_counter: 0,
+            initial_collision_time_stamp: 0,
+        }
+    }
+
+    /// Reset the simulation and start a new episode.
+    ///
+    /// API control is enabled again after the reset.
+    pub async fn reset(&mut self) -> Result<Observation, anyhow::Error> {
+        self.client.reset().await?;
+        self.client.enable_api_control(&self.vehicle_name).await?;
+
+        let observation = self.observe().await?;
+        self.initial_doo_counter = observation.referee.doo_counter;
+        self.initial_collision_time_stamp = observation.collision.time_stamp;
+
+        Ok(observation)
+    }
+
+    /// Apply `action`, wait for `step_duration` and observe the result.
+    ///
+    /// The reward is the car speed in m/s. The episode is done as soon as the
+    /// car collides with something or hits a cone.
+    pub async fn step(
+        &mut self,
+        action: &[f64],
+    ) -> Result<(Observation, f64, bool), anyhow::Error> {
+        self.client
+            .set_car_controls(CarControls::from_action(action), &self.vehicle_name)
+            .await?;
+        tokio::time::sleep(self.step_duration).await;
+
+        let observation = self.observe().await?;
+        let reward = observation.car_state.speed;
+        let done = (observation.collision.has_collided
+            && observation.collision.time_stamp != self.initial_collision_time_stamp)
+            || observation.referee.doo_counter > self.initial_doo_counter;
+
+        Ok((observation, reward, done))
+    }
+
+    /// Access the underlying client.
+    pub fn client(&mut self) -> &mut FSDSClient {
+        &mut self.client
+    }
+
+    async fn observe(&mut self) -> Result<Observation, anyhow::Error> {
+        let car_state = self.client.get_car_state(&self.vehicle_name).await?;
+        let collision = self
+            .client
+            .sim_get_collision_info(&self.vehicle_name)
+            .await?;
+        let referee = self.client.get_referee_state().await?;
+
+        Ok(Observation {
+            car_state,
+            collision,
+            referee,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use msgpack_rpc::Value;
+    use std::sync::{Arc, Mutex};
+
+    /// State served by the mock simulator.
+    #[derive(Default)]
+    struct Sim {
+        methods: Vec<String>,
+        doo_counter: u64,
+        collision: CollisionInfo,
+    }
+
+    #[tokio::test]
+    async fn episode_ends_on_new_cone_or_collision() {
+        let sim = Arc::new(Mutex::new(Sim {
+            // A collision left over from before the episode.
+            collision: CollisionInfo {
+                has_collided: true,
+                time_stamp: 5,
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let served = sim.clone();
+        let server = MockServer::start(move |method, _| {
+            let mut sim = served.lock().unwrap();
+            sim.methods.push(method.to_string());
+            let response = match method {
+                "isApiControlEnabled" => true.into(),
+                "getCarState" => CarState::default().into(),
+                "simGetCollisionInfo" => sim.collision.clone().into(),
+                "getRefereeState" => {
+                    Value::Map(vec![("doo_counter".into(), sim.doo_counter.into())])
+                }
+                _ => Value::Nil,
+            };
+            (Duration::ZERO, Ok(response))
+        })
+        .await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let mut env = CarEnv::new(client, "FSCar", Duration::from_millis(1));
+
+        env.reset().await.unwrap();
+        let (_, _, done) = env.step(&[0.0, 1.0]).await.unwrap();
+        assert!(!done);
+        assert!(sim
+            .lock()
+            .unwrap()
+            .methods
+            .iter()
+            .any(|m| m == "setCarControls"));
+
+        sim.lock().unwrap().doo_counter = 1;
+        let (_, _, done) = env.step(&[0.0, 1.0]).await.unwrap();
+        assert!(done);
+
+        env.reset().await.unwrap();
+        let (_, _, done) = env.step(&[0.0, 1.0]).await.unwrap();
+        assert!(!done);
+
+        sim.lock().unwrap().collision.time_stamp = 9;
+        let (_, _, done) = env.step(&[0.0, 1.0]).await.unwrap();
+        assert!(done);
+    }
+}
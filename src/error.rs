@@ -0,0 +1,111 @@
+//! This module contains `FsdsError`, the typed errors returned by the client
+//! inside `anyhow::Error`.
+//!
+//! Most failures are plain `anyhow` errors. The ones callers may want to
+//! branch on can be recovered with `error.downcast_ref::<FsdsError>()`.
+
+use std::fmt::{self, Display, Formatter};
+
+use msgpack_rpc::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsdsError {
+    /// The server answered `method` with an error.
+    Rpc { method: String, error: ServerError },
+}
+
+impl Display for FsdsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FsdsError::Rpc { method, error } => write!(f, "{} failed: {}", method, error),
+        }
+    }
+}
+
+impl std::error::Error for FsdsError {}
+
+/// The error payload of an RPC response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerError {
+    /// Error code, if the server sent one.
+    pub code: Option<i64>,
+    pub message: String,
+}
+
+impl From<Value> for ServerError {
+    /// Servers send either a string, a `{code, message}` map or a
+    /// `[code, message]` array. Anything else is kept as its text form.
+    fn from(value: Value) -> Self {
+        let unknown = |value: &Value| ServerError {
+            code: None,
+            message: value.to_string(),
+        };
+        match value {
+            Value::String(ref message) => match message.as_str() {
+                Some(message) => ServerError {
+                    code: None,
+                    message: message.to_string(),
+                },
+                None => unknown(&value),
+            },
+            Value::Map(ref map) => {
+                let field = |name| {
+                    map.iter()
+                        .find(|(key, _)| key.as_str() == Some(name))
+                        .map(|(_, value)| value)
+                };
+                match field("message").and_then(Value::as_str) {
+                    Some(message) => ServerError {
+                        code: field("code").and_then(Value::as_i64),
+                        message: message.to_string(),
+                    },
+                    None => unknown(&value),
+                }
+            }
+            Value::Array(ref fields) => match fields.as_slice() {
+                [code, message] if code.is_i64() && message.is_str() => ServerError {
+                    code: code.as_i64(),
+                    message: message.as_str().unwrap_or_default().to_string(),
+                },
+                _ => unknown(&value),
+            },
+            value => unknown(&value),
+        }
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} (code {})", self.message, code),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_are_parsed() {
+        assert_eq!(
+            ServerError::from(Value::from("no such vehicle")),
+            ServerError {
+                code: None,
+                message: "no such vehicle".to_string()
+            }
+        );
+
+        let map = Value::Map(vec![
+            ("code".into(), 3.into()),
+            ("message".into(), "bad argument".into()),
+        ]);
+        assert_eq!(ServerError::from(map).code, Some(3));
+
+        let array = Value::Array(vec![7.into(), "timeout".into()]);
+        assert_eq!(ServerError::from(array).to_string(), "timeout (code 7)");
+
+        assert_eq!(ServerError::from(Value::from(1.5)).message, "1.5");
+    }
+}
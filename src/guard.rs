@@ -0,0 +1,271 @@
+//! This module contains `GuardedStream`, a stream wrapper which rejects
+//! MessagePack responses larger than a configurable limit.
+//!
+//! The incoming bytes are scanned with a minimal MessagePack parser which only
+//! tracks value boundaries. Declared lengths (strings, binaries, arrays and
+//! maps) are checked as soon as their header is read, so an enormous response
+//! is rejected before it is buffered by the RPC layer.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Default maximum size of a response, large enough for high resolution
+/// images.
+pub const DEFAULT_MAX_RESPONSE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// State shared between a `GuardedStream` and the client owning it.
+#[derive(Clone, Debug)]
+pub(crate) struct ResponseGuard {
+    /// Maximum size of a single response, in bytes.
+    pub max_bytes: Arc<AtomicU64>,
+    /// Why the connection was closed, if it was closed by the guard.
+    pub error: Arc<Mutex<Option<String>>>,
+}
+
+impl ResponseGuard {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes: Arc::new(AtomicU64::new(max_bytes)),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// A guard with the same limit and no error, for a new connection.
+    pub fn renew(&self) -> Self {
+        Self {
+            max_bytes: self.max_bytes.clone(),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The error which closed the connection, if any.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+}
+
+/// Incremental scanner finding the size of each top-level MessagePack value.
+#[derive(Default, Debug)]
+struct MessageScanner {
+    /// Header bytes of the value being read.
+    header: Vec<u8>,
+    /// Payload bytes left to skip.
+    skip: u64,
+    /// Values left in each open array or map.
+    remaining: Vec<u64>,
+    /// Bytes of the current top-level value read so far.
+    message_bytes: u64,
+}
+
+impl MessageScanner {
+    /// Scan `data`, failing as soon as a value is known to exceed `max_bytes`.
+    fn scan(&mut self, mut data: &[u8], max_bytes: u64) -> Result<(), String> {
+        while !data.is_empty() {
+            if self.skip > 0 {
+                let n = self.skip.min(data.len() as u64);
+                self.skip -= n;
+                self.message_bytes += n;
+                data = &data[n as usize..];
+                if self.skip == 0 {
+                    self.value_done();
+                }
+                continue;
+            }
+
+            self.header.push(data[0]);
+            self.message_bytes += 1;
+            data = &data[1..];
+
+            let Some((header_len, payload, items)) = Self::parse_header(&self.header)? else {
+                continue;
+            };
+            if self.header.len() < header_len {
+                continue;
+            }
+            self.header.clear();
+
+            let min_size = self.message_bytes + payload + items;
+            if min_size > max_bytes {
+                return Err(format!(
+                    "response of at least {} bytes exceeds max_response_bytes ({})",
+                    min_size, max_bytes
+                ));
+            }
+
+            if payload > 0 {
+                self.skip = payload;
+            } else if items > 0 {
+                self.remaining.push(items);
+            } else {
+                self.value_done();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark the current value as complete.
+    fn value_done(&mut self) {
+        loop {
+            match self.remaining.last_mut() {
+                None => {
+                    self.message_bytes = 0;
+                    return;
+                }
+                Some(remaining) => {
+                    *remaining -= 1;
+                    if *remaining > 0 {
+                        return;
+                    }
+                    self.remaining.pop();
+                }
+            }
+        }
+    }
+
+    /// Parse a (possibly partial) value header.
+    ///
+    /// Returns `(header_len, payload_len, items)`, or `None` if more bytes are
+    /// needed to know the lengths.
+    fn parse_header(header: &[u8]) -> Result<Option<(usize, u64, u64)>, String> {
+        let marker = header[0];
+        // Length of the value size field, and its meaning.
+        let (size_len, extra, kind) = match marker {
+            0x00..=0x7f | 0xc0 | 0xc2 | 0xc3 | 0xe0..=0xff => return Ok(Some((1, 0, 0))),
+            0x80..=0x8f => return Ok(Some((1, 0, 2 * (marker & 0x0f) as u64))),
+            0x90..=0x9f => return Ok(Some((1, 0, (marker & 0x0f) as u64))),
+            0xa0..=0xbf => return Ok(Some((1, (marker & 0x1f) as u64, 0))),
+            0xcc | 0xd0 => return Ok(Some((1, 1, 0))),
+            0xcd | 0xd1 => return Ok(Some((1, 2, 0))),
+            0xca | 0xce | 0xd2 => return Ok(Some((1, 4, 0))),
+            0xcb | 0xcf | 0xd3 => return Ok(Some((1, 8, 0))),
+            0xd4 => return Ok(Some((1, 2, 0))),
+            0xd5 => return Ok(Some((1, 3, 0))),
+            0xd6 => return Ok(Some((1, 5, 0))),
+            0xd7 => return Ok(Some((1, 9, 0))),
+            0xd8 => return Ok(Some((1, 17, 0))),
+            0xc4 | 0xd9 => (1, 0, Kind::Payload),
+            0xc5 | 0xda => (2, 0, Kind::Payload),
+            0xc6 | 0xdb => (4, 0, Kind::Payload),
+            // Extensions carry a type byte after the size.
+            0xc7 => (1, 1, Kind::Payload),
+            0xc8 => (2, 1, Kind::Payload),
+            0xc9 => (4, 1, Kind::Payload),
+            0xdc => (2, 0, Kind::Array),
+            0xdd => (4, 0, Kind::Array),
+            0xde => (2, 0, Kind::Map),
+            0xdf => (4, 0, Kind::Map),
+            0xc1 => return Err("invalid MessagePack marker 0xc1".to_string()),
+        };
+
+        if header.len() < 1 + size_len {
+            return Ok(None);
+        }
+        let size = header[1..1 + size_len]
+            .iter()
+            .fold(0u64, |size, byte| (size << 8) | *byte as u64);
+
+        let header_len = 1 + size_len;
+        Ok(Some(match kind {
+            Kind::Payload => (header_len, size + extra, 0),
+            Kind::Array => (header_len, 0, size),
+            Kind::Map => (header_len, 0, 2 * size),
+        }))
+    }
+}
+
+enum Kind {
+    Payload,
+    Array,
+    Map,
+}
+
+/// A stream which fails reads once a response exceeds the guard limit.
+pub(crate) struct GuardedStream<S> {
+    inner: S,
+    guard: ResponseGuard,
+    scanner: MessageScanner,
+}
+
+impl<S> GuardedStream<S> {
+    pub fn new(inner: S, guard: ResponseGuard) -> Self {
+        Self {
+            inner,
+            guard,
+            scanner: MessageScanner::default(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for GuardedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = result {
+            let max_bytes = this.guard.max_bytes.load(Ordering::Relaxed);
+            if let Err(error) = this.scanner.scan(&buf.filled()[filled..], max_bytes) {
+                *this.guard.error.lock().unwrap() = Some(error.clone());
+                return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, error)));
+            }
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for GuardedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scanner_splits_messages_and_rejects_large_ones() {
+        // [1, 2, nil, bin(100)]
+        let mut small = vec![0x94, 0x01, 0x02, 0xc0, 0xc4, 100];
+        small.extend([0u8; 100]);
+
+        // Many small messages, fed in odd-sized chunks, are accepted.
+        let stream = small.repeat(10);
+        let mut scanner = MessageScanner::default();
+        for chunk in stream.chunks(7) {
+            scanner.scan(chunk, small.len() as u64).unwrap();
+        }
+        assert_eq!(scanner.message_bytes, 0);
+
+        // A binary declaring more bytes than allowed is rejected from its header.
+        let mut scanner = MessageScanner::default();
+        assert!(scanner.scan(&[0xc5, 0x03, 0xe8], 999).is_err());
+    }
+}
@@ -1,11 +1,27 @@
+pub mod arbiter;
 pub mod client;
+#[cfg(feature = "env")]
+pub mod env;
+pub mod error;
+pub mod guard;
+pub mod lockstep;
+#[cfg(any(test, feature = "test-util"))]
+pub mod macros;
+pub mod readset;
+#[cfg(feature = "record")]
+pub mod record;
+pub mod stream;
+pub mod trackers;
+pub mod trajectory;
 pub mod types;
 pub mod utils;
+pub mod watchdog;
+
+#[cfg(test)]
+mod mock;
 
 #[cfg(test)]
 mod tests {
     #[test]
     fn client() {}
-
-    fn types() {}
 }
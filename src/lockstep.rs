@@ -0,0 +1,80 @@
+//! This module contains `SimStepper`, which advances a paused simulation in
+//! fixed steps so that several clients can run in lockstep with the sim clock.
+//!
+//! One client drives the simulation with `SimStepper::step`. Cooperating tasks
+//! in the same process wait for the next tick on a receiver obtained with
+//! `SimStepper::subscribe`. Clients living in other processes can instead wait
+//! for the end of each step with `FSDSClient::wait_until_paused`.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::client::FSDSClient;
+
+/// How often the simulator is asked whether the current step is over.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+pub struct SimStepper {
+    client: FSDSClient,
+    tick: watch::Sender<u64>,
+}
+
+impl SimStepper {
+    /// Pause the simulation and create a stepper at tick 0.
+    pub async fn new(mut client: FSDSClient) -> Result<Self, anyhow::Error> {
+        client.sim_pause(true).await?;
+        let (tick, _) = watch::channel(0);
+
+        Ok(Self { client, tick })
+    }
+
+    /// Run the simulation for `duration` of sim time and wait for it to pause
+    /// again, then notify the subscribers.
+    ///
+    /// Returns the new tick number.
+    pub async fn step(&mut self, duration: Duration) -> Result<u64, anyhow::Error> {
+        self.client.sim_continue_for_time(duration).await?;
+        self.client.wait_until_paused(PAUSE_POLL_INTERVAL).await?;
+
+        self.tick.send_modify(|tick| *tick += 1);
+        Ok(*self.tick.borrow())
+    }
+
+    /// Receiver notified with the tick number at the end of every step.
+    ///
+    /// Use `watch::Receiver::changed` to wait for the next tick.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.tick.subscribe()
+    }
+
+    /// Resume the simulation and give the client back.
+    pub async fn finish(mut self) -> Result<FSDSClient, anyhow::Error> {
+        self.client.sim_pause(false).await?;
+        Ok(self.client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use msgpack_rpc::Value;
+
+    #[tokio::test]
+    async fn step_notifies_subscribers() {
+        let server = MockServer::start(|method, _| match method {
+            "simIsPaused" => (Duration::ZERO, Ok(Value::from(true))),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let mut stepper = SimStepper::new(client).await.unwrap();
+        let mut ticks = stepper.subscribe();
+
+        assert_eq!(stepper.step(Duration::from_millis(10)).await.unwrap(), 1);
+        ticks.changed().await.unwrap();
+        assert_eq!(*ticks.borrow(), 1);
+    }
+}
@@ -0,0 +1,50 @@
+//! Assertion macros comparing `Vector3r` and `Quaternionr` values with a
+//! tolerance, available with the `test-util` feature.
+
+/// Default tolerance of the approximate-equality assertions.
+pub const DEFAULT_EPSILON: f64 = 1e-9;
+
+/// Assert that two `Vector3r` are equal within a tolerance.
+///
+/// The tolerance defaults to `DEFAULT_EPSILON` and applies to each component.
+#[macro_export]
+macro_rules! assert_vec_approx_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::assert_vec_approx_eq!($left, $right, $crate::macros::DEFAULT_EPSILON)
+    };
+    ($left:expr, $right:expr, $epsilon:expr $(,)?) => {{
+        let (left, right): (&$crate::types::Vector3r, &$crate::types::Vector3r) = (&$left, &$right);
+        let epsilon: f64 = $epsilon;
+        assert!(
+            left.approx_eq(right, epsilon),
+            "assertion failed: vectors differ by more than {}\n  left: {:?}\n right: {:?}",
+            epsilon,
+            left,
+            right
+        );
+    }};
+}
+
+/// Assert that two `Quaternionr` are equal within a tolerance.
+///
+/// The tolerance defaults to `DEFAULT_EPSILON` and applies to each component.
+/// Note that `q` and `-q` represent the same rotation but are not considered
+/// equal.
+#[macro_export]
+macro_rules! assert_quat_approx_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::assert_quat_approx_eq!($left, $right, $crate::macros::DEFAULT_EPSILON)
+    };
+    ($left:expr, $right:expr, $epsilon:expr $(,)?) => {{
+        let (left, right): (&$crate::types::Quaternionr, &$crate::types::Quaternionr) =
+            (&$left, &$right);
+        let epsilon: f64 = $epsilon;
+        assert!(
+            left.approx_eq(right, epsilon),
+            "assertion failed: quaternions differ by more than {}\n  left: {:?}\n right: {:?}",
+            epsilon,
+            left,
+            right
+        );
+    }};
+}
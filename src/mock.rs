@@ -0,0 +1,59 @@
+//! A minimal msgpack-rpc server used to exercise the client in tests.
+
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use msgpack_rpc::{Service, Value};
+use tokio::net::TcpListener;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+/// Handler deciding how long to wait and what to answer for each request.
+type Handler = dyn Fn(&str, &[Value]) -> (Duration, Result<Value, Value>) + Send + Sync;
+
+#[derive(Clone)]
+struct MockService {
+    handler: Arc<Handler>,
+}
+
+impl Service for MockService {
+    type RequestFuture = Pin<Box<dyn Future<Output = Result<Value, Value>> + Send>>;
+
+    fn handle_request(&mut self, method: &str, params: &[Value]) -> Self::RequestFuture {
+        let (delay, response) = (self.handler)(method, params);
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            response
+        })
+    }
+
+    fn handle_notification(&mut self, _method: &str, _params: &[Value]) {}
+}
+
+/// A server listening on a random local port, answering with `handler`.
+pub struct MockServer {
+    addr: SocketAddr,
+}
+
+impl MockServer {
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &[Value]) -> (Duration, Result<Value, Value>) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = MockService {
+            handler: Arc::new(handler),
+        };
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(msgpack_rpc::serve(stream.compat(), service.clone()));
+            }
+        });
+
+        Self { addr }
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+}
@@ -0,0 +1,187 @@
+//! This module contains `ReadSet`, a builder for synchronized reads of several
+//! sensors at once.
+//!
+//! Declare what to read, e.g. `ReadSet::new().kinematics().imu()
+//! .image("0", ImageType::Scene)`, then `fetch` sends every request before
+//! waiting for any response, so all the readings are taken as close in time
+//! as the simulator allows.
+
+use msgpack_rpc::Value;
+
+use crate::{
+    client::{image_responses_from_value, FSDSClient},
+    types::{
+        FrameConvert, GpsData, ImageRequest, ImageResponse, ImageType, ImuData, KinematicsState,
+        LidarData,
+    },
+};
+
+/// The sensors to read, see the module documentation.
+#[derive(Clone, Default, Debug)]
+pub struct ReadSet {
+    kinematics: bool,
+    imu: Option<String>,
+    gps: Option<String>,
+    lidar: Option<String>,
+    images: Vec<ImageRequest>,
+}
+
+/// The result of `ReadSet::fetch`, only the requested fields are set.
+#[derive(Clone, Default, Debug)]
+pub struct Readings {
+    pub kinematics: Option<KinematicsState>,
+    pub imu: Option<ImuData>,
+    pub gps: Option<GpsData>,
+    pub lidar: Option<LidarData>,
+    /// Images, in the order they were added.
+    pub images: Option<Vec<ImageResponse>>,
+}
+
+impl ReadSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the ground truth kinematics.
+    pub fn kinematics(mut self) -> Self {
+        self.kinematics = true;
+        self
+    }
+
+    /// Read the first IMU of the vehicle.
+    pub fn imu(self) -> Self {
+        self.imu_named("")
+    }
+
+    /// Read the IMU named `imu_name`.
+    pub fn imu_named(mut self, imu_name: &str) -> Self {
+        self.imu = Some(imu_name.to_string());
+        self
+    }
+
+    /// Read the first GPS of the vehicle.
+    pub fn gps(mut self) -> Self {
+        self.gps = Some(String::new());
+        self
+    }
+
+    /// Read the lidar named `lidar_name`, replacing any lidar added before.
+    pub fn lidar(mut self, lidar_name: &str) -> Self {
+        self.lidar = Some(lidar_name.to_string());
+        self
+    }
+
+    /// Read an uncompressed image of `camera_name`.
+    pub fn image(self, camera_name: &str, image_type: ImageType) -> Self {
+        self.image_request(ImageRequest {
+            camera_name: camera_name.to_string(),
+            image_type,
+            pixels_as_float: false,
+            compress: false,
+        })
+    }
+
+    /// Read an image with custom options.
+    pub fn image_request(mut self, request: ImageRequest) -> Self {
+        self.images.push(request);
+        self
+    }
+
+    /// Read every requested sensor of `vehicle_name`.
+    ///
+    /// All the images are captured by a single `simGetImages` call. Fails if
+    /// any of the reads fails.
+    pub async fn fetch(
+        &self,
+        client: &mut FSDSClient,
+        vehicle_name: &str,
+    ) -> Result<Readings, anyhow::Error> {
+        let mut calls = Vec::new();
+        if self.kinematics {
+            calls.push(("simGetGroundTruthKinematics", vec![vehicle_name.into()]));
+        }
+        let named = |name: &str| vec![name.into(), vehicle_name.into()];
+        if let Some(imu_name) = &self.imu {
+            calls.push(("getImuData", named(imu_name)));
+        }
+        if let Some(gps_name) = &self.gps {
+            calls.push(("getGpsData", named(gps_name)));
+        }
+        if let Some(lidar_name) = &self.lidar {
+            calls.push(("getLidarData", named(lidar_name)));
+        }
+        if !self.images.is_empty() {
+            let requests = self.images.iter().map(|r| r.clone().into()).collect();
+            calls.push((
+                "simGetImages",
+                vec![Value::Array(requests), vehicle_name.into()],
+            ));
+        }
+
+        let mut responses = client.request_batch(&calls).await?.into_iter();
+        let mut next = || responses.next().unwrap_or(Value::Nil);
+
+        let mut readings = Readings::default();
+        if self.kinematics {
+            readings.kinematics = Some(client.decode_in_frame(next())?);
+        }
+        if self.imu.is_some() {
+            readings.imu = Some(client.decode_in_frame(next())?);
+        }
+        if self.gps.is_some() {
+            readings.gps = Some(client.decode_in_frame(next())?);
+        }
+        if self.lidar.is_some() {
+            readings.lidar = Some(LidarData::try_from(next())?.to_frame(client.output_frame()));
+        }
+        if !self.images.is_empty() {
+            readings.images = Some(image_responses_from_value(next(), &self.images)?);
+        }
+
+        Ok(readings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn requested_sensors_are_read_together() {
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let recorded = methods.clone();
+        let server = MockServer::start(move |method, _| {
+            recorded.lock().unwrap().push(method.to_string());
+            match method {
+                "simGetGroundTruthKinematics" => {
+                    (Duration::ZERO, Ok(KinematicsState::default().into()))
+                }
+                "getImuData" => (Duration::ZERO, Ok(ImuData::default().into())),
+                _ => (Duration::ZERO, Ok(Value::Nil)),
+            }
+        })
+        .await;
+        let mut client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        methods.lock().unwrap().clear();
+
+        let readings = ReadSet::new()
+            .kinematics()
+            .imu()
+            .fetch(&mut client, "FSCar")
+            .await
+            .unwrap();
+
+        assert!(readings.kinematics.is_some());
+        assert!(readings.imu.is_some());
+        assert!(readings.gps.is_none() && readings.lidar.is_none() && readings.images.is_none());
+        assert_eq!(
+            *methods.lock().unwrap(),
+            ["simGetGroundTruthKinematics", "getImuData"]
+        );
+    }
+}
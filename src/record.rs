@@ -0,0 +1,192 @@
+//! This module contains `Recorder`, which logs the RPC traffic of a client to
+//! a file, and `ReplayClient`, which serves a recorded session back without a
+//! running simulator.
+//!
+//! A recording is a sequence of MessagePack arrays, one per call:
+//! `[method, params, ok, response]` where `response` is the returned value if
+//! `ok` is true and the error message otherwise.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Cursor, Write},
+    path::Path,
+};
+
+use msgpack_rpc::Value;
+
+use crate::client::{ApiFuture, FsdsApi};
+
+/// Wraps an API and writes every call and its response to a file.
+pub struct Recorder<A> {
+    inner: A,
+    writer: BufWriter<File>,
+}
+
+impl<A: FsdsApi + Send> Recorder<A> {
+    /// Record the calls made through `inner` to `path`, truncating it.
+    pub fn create(inner: A, path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            inner,
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Stop recording and give the wrapped API back.
+    pub fn into_inner(mut self) -> Result<A, anyhow::Error> {
+        self.writer.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_entry(
+        &mut self,
+        method: &str,
+        params: &[Value],
+        response: &Result<Value, anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let (ok, response) = match response {
+            Ok(value) => (true, value.clone()),
+            Err(error) => (false, error.to_string().into()),
+        };
+        let entry = Value::Array(vec![
+            method.into(),
+            Value::Array(params.to_vec()),
+            ok.into(),
+            response,
+        ]);
+
+        rmpv::encode::write_value(&mut self.writer, &entry)?;
+        // Flush every call so that a crash does not lose the end of the run.
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<A: FsdsApi + Send> FsdsApi for Recorder<A> {
+    fn call<'a>(&'a mut self, method: &'a str, params: &'a [Value]) -> ApiFuture<'a> {
+        Box::pin(async move {
+            let response = self.inner.call(method, params).await;
+            self.write_entry(method, params, &response)?;
+            response
+        })
+    }
+}
+
+/// A recorded call.
+struct Entry {
+    method: String,
+    params: Vec<Value>,
+    response: Result<Value, String>,
+}
+
+/// Serves the responses of a recording, in order.
+///
+/// Each call must use the same method as the next recorded call, otherwise it
+/// fails: the run diverged from the recording. Differing parameters only log
+/// a warning, since e.g. controls computed from floats may differ slightly.
+pub struct ReplayClient {
+    entries: VecDeque<Entry>,
+}
+
+impl ReplayClient {
+    /// Load a recording made by `Recorder`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let bytes = std::fs::read(path)?;
+        let len = bytes.len() as u64;
+        let mut reader = Cursor::new(bytes);
+
+        let mut entries = VecDeque::new();
+        while reader.position() < len {
+            let entry = rmpv::decode::read_value(&mut reader)?;
+            entries.push_back(Self::parse_entry(entry)?);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Number of recorded calls not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn parse_entry(entry: Value) -> Result<Entry, anyhow::Error> {
+        let invalid = || anyhow::anyhow!("Invalid recording entry");
+        let Value::Array(fields) = entry else {
+            return Err(invalid());
+        };
+        let [method, Value::Array(params), Value::Boolean(ok), response] =
+            <[Value; 4]>::try_from(fields).map_err(|_| invalid())?
+        else {
+            return Err(invalid());
+        };
+
+        let method = String::try_from(method).map_err(|_| invalid())?;
+        let response = if ok {
+            Ok(response)
+        } else {
+            Err(String::try_from(response).map_err(|_| invalid())?)
+        };
+
+        Ok(Entry {
+            method,
+            params,
+            response,
+        })
+    }
+}
+
+impl FsdsApi for ReplayClient {
+    fn call<'a>(&'a mut self, method: &'a str, params: &'a [Value]) -> ApiFuture<'a> {
+        Box::pin(async move {
+            let entry = self.entries.pop_front().ok_or(anyhow::anyhow!(
+                "{} called after the end of the recording",
+                method
+            ))?;
+            if entry.method != method {
+                return Err(anyhow::anyhow!(
+                    "Replay diverged: expected {}, got {}",
+                    entry.method,
+                    method
+                ));
+            }
+            if entry.params != params {
+                tracing::warn!("{} replayed with different parameters", method);
+            }
+
+            entry.response.map_err(|error| anyhow::anyhow!(error))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::FSDSClient, mock::MockServer};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn recorded_session_is_replayed_in_order() {
+        let server = MockServer::start(|method, _| match method {
+            "getRefereeState" => (Duration::ZERO, Ok(Value::Map(vec![]))),
+            _ => (Duration::ZERO, Err("unknown method".into())),
+        })
+        .await;
+        let path = std::env::temp_dir().join(format!("fsds-rs-record-{}", std::process::id()));
+
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let mut recorder = Recorder::create(client, &path).unwrap();
+        recorder.call("getRefereeState", &[]).await.unwrap();
+        recorder.call("ping", &[]).await.unwrap_err();
+        recorder.into_inner().unwrap();
+
+        let mut replay = ReplayClient::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.remaining(), 2);
+        assert_eq!(
+            replay.call("getRefereeState", &[]).await.unwrap(),
+            Value::Map(vec![])
+        );
+        assert!(replay.call("reset", &[]).await.is_err());
+        assert!(replay.call("ping", &[]).await.is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! This module contains `ImageStream`, which polls camera images at a fixed
+//! rate, `FrameMonitor`, which detects frames dropped by the simulator, and
+//! `TelemetryStream`, which pushes sensor readings to a synchronous channel.
+
+use std::{
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use tokio::time::{Interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::FSDSClient,
+    readset::{ReadSet, Readings},
+    types::{ImageRequest, ImageResponse},
+};
+
+/// Counters about the frames received by a stream.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct FrameStats {
+    /// Frames received.
+    pub frames: u64,
+    /// Frames estimated to be missing between the received ones.
+    pub dropped: u64,
+    /// Frames which did not arrive within the stream frame timeout.
+    pub timeouts: u64,
+}
+
+/// Detects gaps between consecutive frames.
+///
+/// A gap is reported when two frame time stamps are more than 1.5 times the
+/// nominal period apart. Time stamps are the sim-time nanoseconds of
+/// `ImageResponse::time_stamp`.
+#[derive(Clone, Debug)]
+pub struct FrameMonitor {
+    period: Duration,
+    last_time_stamp: Option<u64>,
+    stats: FrameStats,
+}
+
+impl FrameMonitor {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            last_time_stamp: None,
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Record a frame and return how many frames were dropped before it.
+    pub fn record(&mut self, time_stamp: u64) -> u64 {
+        let period = self.period.as_nanos() as f64;
+        let dropped = match self.last_time_stamp {
+            Some(last) if period > 0.0 => {
+                let gap = time_stamp.saturating_sub(last) as f64;
+                if gap > 1.5 * period {
+                    ((gap / period).round() as u64).saturating_sub(1).max(1)
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+
+        if dropped > 0 {
+            tracing::warn!(dropped, time_stamp, "Simulator dropped camera frames");
+        }
+
+        self.last_time_stamp = Some(time_stamp);
+        self.stats.frames += 1;
+        self.stats.dropped += dropped;
+        dropped
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
+
+/// Fetches the same set of images at a fixed rate.
+pub struct ImageStream {
+    client: FSDSClient,
+    requests: Vec<ImageRequest>,
+    vehicle_name: String,
+    interval: Interval,
+    monitor: FrameMonitor,
+    frame_timeout: Option<Duration>,
+}
+
+impl ImageStream {
+    /// Create a stream fetching `requests` every `period`.
+    ///
+    /// The stream uses its own clone of `client`, sharing its connection.
+    pub fn new(
+        client: &FSDSClient,
+        requests: Vec<ImageRequest>,
+        vehicle_name: &str,
+        period: Duration,
+    ) -> Self {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            client: client.clone(),
+            requests,
+            vehicle_name: vehicle_name.to_string(),
+            interval,
+            monitor: FrameMonitor::new(period),
+            frame_timeout: None,
+        }
+    }
+
+    /// Fail a frame which takes longer than `timeout` to arrive, `None` (the
+    /// default) waits forever.
+    pub fn set_frame_timeout(&mut self, timeout: Option<Duration>) {
+        self.frame_timeout = timeout;
+    }
+
+    /// Wait for the next tick and fetch the images.
+    ///
+    /// The first response time stamp is used to detect dropped frames. If the
+    /// frame timeout expires, an error is returned and the next call fetches
+    /// a new frame. Dropping the returned future stops polling right away:
+    /// the late response, if any, is discarded by the connection.
+    pub async fn next_frame(&mut self) -> Result<Vec<ImageResponse>, anyhow::Error> {
+        self.interval.tick().await;
+
+        let request = self
+            .client
+            .sim_get_image_responses(&self.requests, &self.vehicle_name);
+        let images = match self.frame_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, request).await {
+                Ok(images) => images?,
+                Err(_) => {
+                    self.monitor.stats.timeouts += 1;
+                    return Err(anyhow::anyhow!("No frame received within {:?}", timeout));
+                }
+            },
+            None => request.await?,
+        };
+        if let Some(image) = images.first() {
+            self.monitor.record(image.time_stamp);
+        }
+
+        Ok(images)
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.monitor.stats()
+    }
+}
+
+/// What a `TelemetryStream` reads.
+#[derive(Clone, Debug)]
+pub struct TelemetryConfig {
+    pub vehicle_name: String,
+    /// Time between two reads.
+    pub period: Duration,
+    /// The sensors to read every period.
+    pub reads: ReadSet,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            vehicle_name: "FSCar".to_string(),
+            period: Duration::from_millis(50),
+            reads: ReadSet::new().kinematics(),
+        }
+    }
+}
+
+/// A set of readings sent by a `TelemetryStream`.
+#[derive(Clone, Debug)]
+pub struct Telemetry {
+    /// When the readings were received.
+    pub received_at: Instant,
+    pub readings: Readings,
+}
+
+/// Reads telemetry in a background task and sends it to a
+/// `std::sync::mpsc` channel, for synchronous main loops.
+///
+/// Reads which fail are logged and skipped. The stream stops when dropped or
+/// when the receiver is dropped.
+pub struct TelemetryStream {
+    stop: CancellationToken,
+}
+
+impl TelemetryStream {
+    /// Start reading `config.reads` every `config.period` with a clone of
+    /// `client` and sending them to `tx`.
+    pub fn spawn(client: &FSDSClient, tx: Sender<Telemetry>, config: TelemetryConfig) -> Self {
+        let stop = CancellationToken::new();
+
+        let mut client = client.clone();
+        let task_stop = stop.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = task_stop.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let readings = match config.reads.fetch(&mut client, &config.vehicle_name).await {
+                    Ok(readings) => readings,
+                    Err(e) => {
+                        tracing::warn!("Telemetry read failed: {}", e);
+                        continue;
+                    }
+                };
+                let telemetry = Telemetry {
+                    received_at: Instant::now(),
+                    readings,
+                };
+                if tx.send(telemetry).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self { stop }
+    }
+}
+
+impl Drop for TelemetryStream {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use msgpack_rpc::Value;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn frame_monitor_counts_gaps() {
+        let mut monitor = FrameMonitor::new(Duration::from_nanos(100));
+
+        assert_eq!(monitor.record(1_000), 0);
+        assert_eq!(monitor.record(1_140), 0);
+        assert_eq!(monitor.record(1_400), 2);
+
+        let stats = monitor.stats();
+        assert_eq!((stats.frames, stats.dropped), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn stalled_frames_time_out_and_stream_recovers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let server_calls = calls.clone();
+        let server = MockServer::start(move |method, _| {
+            if method != "simGetImages" {
+                return (Duration::ZERO, Ok(Value::Nil));
+            }
+            // The first two frames stall, the third one arrives after the
+            // late answer to the first.
+            let call = server_calls.fetch_add(1, Ordering::SeqCst);
+            let delay = match call {
+                0 => Duration::from_millis(150),
+                1 => Duration::from_secs(5),
+                _ => Duration::from_millis(150),
+            };
+            let response = ImageResponse {
+                time_stamp: call as u64 + 1,
+                image_data_uint8: vec![0; 3],
+                width: 1,
+                height: 1,
+                compress: false,
+                ..Default::default()
+            };
+            (delay, Ok(Value::Array(vec![response.into()])))
+        })
+        .await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let requests = vec![ImageRequest::default()];
+        let mut stream = ImageStream::new(&client, requests, "FSCar", Duration::from_millis(10));
+
+        // Dropping the frame future does not wedge the stream.
+        let dropped = tokio::time::timeout(Duration::from_millis(50), stream.next_frame()).await;
+        assert!(dropped.is_err());
+
+        stream.set_frame_timeout(Some(Duration::from_millis(50)));
+        assert!(stream.next_frame().await.is_err());
+
+        // The late answers to the dropped and timed out frames are discarded.
+        stream.set_frame_timeout(None);
+        let images = stream.next_frame().await.unwrap();
+        assert_eq!(images[0].time_stamp, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(stream.stats().timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn telemetry_reaches_a_sync_receiver() {
+        let server = MockServer::start(|method, _| match method {
+            "simGetGroundTruthKinematics" => (
+                Duration::ZERO,
+                Ok(crate::types::KinematicsState::default().into()),
+            ),
+            _ => (Duration::ZERO, Ok(Value::Nil)),
+        })
+        .await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let config = TelemetryConfig {
+            period: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let _stream = client.spawn_telemetry_stream(tx, config);
+
+        let telemetry = tokio::task::spawn_blocking(move || rx.recv().unwrap())
+            .await
+            .unwrap();
+        assert!(telemetry.readings.kinematics.is_some());
+    }
+}
@@ -0,0 +1,243 @@
+//! This module contains trackers which aggregate successive reads from the
+//! simulator into statistics, e.g. to score a run.
+
+use std::collections::VecDeque;
+
+use crate::{
+    client::FSDSClient,
+    types::{CollisionInfo, RefereeState},
+};
+
+/// Counts the collisions of a vehicle and keeps the most recent ones.
+///
+/// The simulator only reports the last collision, so the tracker must be
+/// polled often enough not to miss any. Collisions are deduplicated by their
+/// `time_stamp`.
+#[derive(Clone, Debug)]
+pub struct CollisionTracker {
+    vehicle_name: String,
+    capacity: usize,
+    count: u64,
+    last_time_stamp: Option<u64>,
+    history: VecDeque<CollisionInfo>,
+}
+
+impl CollisionTracker {
+    /// Create a tracker keeping at most `capacity` collisions in its history.
+    pub fn new(vehicle_name: &str, capacity: usize) -> Self {
+        Self {
+            vehicle_name: vehicle_name.to_string(),
+            capacity,
+            count: 0,
+            last_time_stamp: None,
+            history: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Read the collision info and record it.
+    ///
+    /// Returns the collision if it is a new one.
+    pub async fn poll(
+        &mut self,
+        client: &mut FSDSClient,
+    ) -> Result<Option<CollisionInfo>, anyhow::Error> {
+        let info = client.sim_get_collision_info(&self.vehicle_name).await?;
+
+        Ok(self.record(info.clone()).then_some(info))
+    }
+
+    /// Record a collision info, returns true if it is a new collision.
+    pub fn record(&mut self, info: CollisionInfo) -> bool {
+        if !info.has_collided || self.last_time_stamp == Some(info.time_stamp) {
+            return false;
+        }
+
+        self.last_time_stamp = Some(info.time_stamp);
+        self.count += 1;
+        if self.capacity > 0 {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(info);
+        }
+
+        true
+    }
+
+    /// Number of distinct collisions seen so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The most recent collisions, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &CollisionInfo> {
+        self.history.iter()
+    }
+
+    /// Forget every collision seen so far.
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.history.clear();
+    }
+}
+
+/// Number of laps of the trackdrive event.
+pub const TRACKDRIVE_LAPS: u32 = 10;
+
+/// Splits the cumulative `doo_counter` of the referee into cones hit per lap,
+/// and detects the end of the mission.
+///
+/// The simulator cannot reset the counter, so the tracker snapshots it every
+/// time the `laps` count increases and reports the difference.
+#[derive(Clone, Default, Debug)]
+pub struct RefereeStateTracker {
+    /// `laps` and `doo_counter` at the start of the current lap.
+    lap_start: Option<(f64, u64)>,
+    doo_counter: u64,
+    per_lap: Vec<u64>,
+    required_laps: Option<u32>,
+    /// Whether the last recorded state completed the required laps.
+    just_finished: bool,
+}
+
+impl RefereeStateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Laps to complete before the mission is finished, e.g.
+    /// `TRACKDRIVE_LAPS`. `None` (the default) never finishes.
+    pub fn set_required_laps(&mut self, laps: Option<u32>) {
+        self.required_laps = laps;
+    }
+
+    /// Whether the required laps are completed.
+    pub fn finished(&self) -> bool {
+        let laps = self.lap_start.map_or(0.0, |(laps, _)| laps);
+        self.required_laps
+            .is_some_and(|required| laps >= required as f64)
+    }
+
+    /// Whether the last recorded state completed the required laps: true
+    /// once per mission, to trigger the finish sequence.
+    pub fn just_finished(&self) -> bool {
+        self.just_finished
+    }
+
+    /// Read the referee state and record it.
+    ///
+    /// Returns true if a lap was completed since the previous poll.
+    pub async fn poll(&mut self, client: &mut FSDSClient) -> Result<bool, anyhow::Error> {
+        let state = client.get_referee_state().await?;
+        Ok(self.record(&state))
+    }
+
+    /// Record a referee state, returns true if a lap was completed since the
+    /// previous one.
+    ///
+    /// The first state recorded starts the current lap.
+    pub fn record(&mut self, state: &RefereeState) -> bool {
+        self.doo_counter = state.doo_counter;
+        self.just_finished = false;
+        let Some((laps, doo_counter)) = self.lap_start else {
+            self.lap_start = Some((state.laps, state.doo_counter));
+            return false;
+        };
+        if state.laps <= laps {
+            return false;
+        }
+
+        let was_finished = self.finished();
+        self.per_lap
+            .push(state.doo_counter.saturating_sub(doo_counter));
+        self.lap_start = Some((state.laps, state.doo_counter));
+        self.just_finished = !was_finished && self.finished();
+        true
+    }
+
+    /// Cones hit since the start of the current lap.
+    pub fn cones_hit_this_lap(&self) -> u64 {
+        self.lap_start.map_or(0, |(_, doo_counter)| {
+            self.doo_counter.saturating_sub(doo_counter)
+        })
+    }
+
+    /// Cones hit during each completed lap, in order.
+    pub fn cones_hit_per_lap(&self) -> &[u64] {
+        &self.per_lap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Vector3r;
+
+    fn collision(time_stamp: u64) -> CollisionInfo {
+        CollisionInfo {
+            has_collided: true,
+            normal: Vector3r::default(),
+            impact_point: Vector3r::default(),
+            position: Vector3r::default(),
+            penetration_depth: 0.0,
+            time_stamp,
+            object_name: "cone".to_string(),
+            object_id: 1,
+        }
+    }
+
+    #[test]
+    fn collision_tracker_deduplicates_and_bounds_history() {
+        let mut tracker = CollisionTracker::new("FSCar", 2);
+
+        assert!(tracker.record(collision(1)));
+        assert!(!tracker.record(collision(1)));
+        assert!(tracker.record(collision(2)));
+        assert!(tracker.record(collision(3)));
+
+        assert_eq!(tracker.count(), 3);
+        let history: Vec<_> = tracker.history().map(|c| c.time_stamp).collect();
+        assert_eq!(history, vec![2, 3]);
+    }
+
+    #[test]
+    fn referee_tracker_splits_cones_per_lap() {
+        let state = |laps, doo_counter| RefereeState {
+            laps,
+            doo_counter,
+            ..Default::default()
+        };
+        let mut tracker = RefereeStateTracker::new();
+
+        assert!(!tracker.record(&state(0.0, 1)));
+        assert!(!tracker.record(&state(0.0, 3)));
+        assert_eq!(tracker.cones_hit_this_lap(), 2);
+
+        assert!(tracker.record(&state(1.0, 4)));
+        assert_eq!(tracker.cones_hit_this_lap(), 0);
+        assert!(!tracker.record(&state(1.0, 5)));
+        assert!(tracker.record(&state(2.0, 5)));
+
+        assert_eq!(tracker.cones_hit_per_lap(), [3, 1]);
+    }
+
+    #[test]
+    fn referee_tracker_detects_the_finish_once() {
+        let state = |laps| RefereeState {
+            laps,
+            ..Default::default()
+        };
+        let mut tracker = RefereeStateTracker::new();
+        tracker.set_required_laps(Some(2));
+
+        tracker.record(&state(0.0));
+        tracker.record(&state(1.0));
+        assert!(!tracker.finished());
+        tracker.record(&state(2.0));
+        assert!(tracker.finished() && tracker.just_finished());
+        tracker.record(&state(2.0));
+        assert!(tracker.finished() && !tracker.just_finished());
+        tracker.record(&state(3.0));
+        assert!(!tracker.just_finished());
+    }
+}
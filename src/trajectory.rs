@@ -0,0 +1,212 @@
+//! This module contains `Trajectory`, a sequence of time stamped poses which
+//! can be sampled at any time or distance along the path.
+//!
+//! Positions are interpolated linearly and orientations with a slerp, see
+//! `Pose::interpolate`.
+
+use crate::types::{Pose, Vector3r};
+
+pub struct Trajectory {
+    /// Time stamps, in seconds, sorted in increasing order.
+    times: Vec<f64>,
+    poses: Vec<Pose>,
+    /// Distance travelled from the first pose to each pose, in meters.
+    distances: Vec<f64>,
+}
+
+impl Trajectory {
+    /// Build a trajectory from `(t, pose)` samples, e.g. a recorded run.
+    ///
+    /// Fails if there are no samples or if the time stamps are not strictly
+    /// increasing.
+    pub fn new(samples: Vec<(f64, Pose)>) -> Result<Self, anyhow::Error> {
+        if samples.is_empty() {
+            return Err(anyhow::anyhow!("A trajectory needs at least one pose"));
+        }
+        if samples
+            .windows(2)
+            .any(|w| w[0].0.partial_cmp(&w[1].0) != Some(std::cmp::Ordering::Less))
+        {
+            return Err(anyhow::anyhow!(
+                "Trajectory time stamps should be strictly increasing"
+            ));
+        }
+
+        let (times, poses): (Vec<f64>, Vec<Pose>) = samples.into_iter().unzip();
+        let mut distances = Vec::with_capacity(poses.len());
+        let mut distance = 0.0;
+        for (i, pose) in poses.iter().enumerate() {
+            if i > 0 {
+                distance += pose.position.distance_to(&poses[i - 1].position);
+            }
+            distances.push(distance);
+        }
+
+        Ok(Self {
+            times,
+            poses,
+            distances,
+        })
+    }
+
+    /// Time of the first and last poses.
+    pub fn time_range(&self) -> (f64, f64) {
+        (self.times[0], self.times[self.times.len() - 1])
+    }
+
+    /// Length of the path, in meters.
+    pub fn length(&self) -> f64 {
+        self.distances[self.distances.len() - 1]
+    }
+
+    /// The pose at time `t`.
+    ///
+    /// `t` outside of the recorded range is clamped to the first or last pose.
+    pub fn sample(&self, t: f64) -> Pose {
+        Self::sample_along(&self.times, &self.poses, t)
+    }
+
+    /// The pose at `distance` meters along the path from the first pose.
+    ///
+    /// `distance` outside of `[0, length]` is clamped to the first or last
+    /// pose. While the car is standing still, the last pose at that distance
+    /// is returned.
+    pub fn sample_by_distance(&self, distance: f64) -> Pose {
+        Self::sample_along(&self.distances, &self.poses, distance)
+    }
+
+    /// Every pose with its linear and angular velocities, estimated by finite
+    /// differences: central for inner poses, forward for the first pose and
+    /// backward for the last one.
+    ///
+    /// Both velocities are in the world frame, the angular velocity is the
+    /// rotation between the two orientations around a fixed axis. A
+    /// trajectory with a single pose has zero velocities.
+    pub fn with_velocities(&self) -> Vec<(Pose, Vector3r, Vector3r)> {
+        let last = self.poses.len() - 1;
+        (0..=last)
+            .map(|i| {
+                if last == 0 {
+                    return (self.poses[i], Vector3r::default(), Vector3r::default());
+                }
+                let (from, to) = (i.saturating_sub(1), (i + 1).min(last));
+                let dt = self.times[to] - self.times[from];
+                let (a, b) = (self.poses[from], self.poses[to]);
+
+                let mut linear = b.position - a.position;
+                linear /= dt;
+
+                let mut delta = b.orientation * a.orientation.conjugate();
+                // Take the shortest rotation.
+                if delta.w_val < 0.0 {
+                    delta = delta.conjugate();
+                    delta.w_val = -delta.w_val;
+                }
+                let axis = Vector3r {
+                    x_val: delta.x_val,
+                    y_val: delta.y_val,
+                    z_val: delta.z_val,
+                };
+                let sin_half = axis.get_length();
+                let mut angular = axis;
+                if sin_half > f64::EPSILON {
+                    angular *= 2.0 * sin_half.atan2(delta.w_val) / (sin_half * dt);
+                } else {
+                    angular *= 2.0 / dt;
+                }
+
+                (self.poses[i], linear, angular)
+            })
+            .collect()
+    }
+
+    /// Interpolate `poses` at `x`, where `keys` are sorted and match `poses`.
+    fn sample_along(keys: &[f64], poses: &[Pose], x: f64) -> Pose {
+        // Index of the first key strictly greater than `x`.
+        let next = keys.partition_point(|key| *key <= x);
+        if next == 0 {
+            return poses[0];
+        }
+        if next == keys.len() {
+            return poses[keys.len() - 1];
+        }
+
+        let previous = next - 1;
+        let span = keys[next] - keys[previous];
+        if span <= 0.0 {
+            return poses[previous];
+        }
+        poses[previous].interpolate(&poses[next], (x - keys[previous]) / span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Quaternionr, Vector3r};
+
+    fn pose(x: f64, yaw: f64) -> Pose {
+        Pose::new(
+            Some(Vector3r {
+                x_val: x,
+                ..Default::default()
+            }),
+            Some(Quaternionr {
+                w_val: (yaw / 2.0).cos(),
+                z_val: (yaw / 2.0).sin(),
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn sample_interpolates_and_clamps() {
+        let trajectory = Trajectory::new(vec![
+            (0.0, pose(0.0, 0.0)),
+            (1.0, pose(2.0, 0.0)),
+            (3.0, pose(4.0, std::f64::consts::PI / 2.0)),
+        ])
+        .unwrap();
+
+        assert!(trajectory
+            .sample(0.5)
+            .position
+            .approx_eq(&pose(1.0, 0.0).position, 1e-9));
+        assert!(trajectory
+            .sample(2.0)
+            .orientation
+            .approx_eq(&pose(3.0, std::f64::consts::PI / 4.0).orientation, 1e-9));
+        assert_eq!(trajectory.sample(-1.0).position.x_val, 0.0);
+        assert_eq!(trajectory.sample(10.0).position.x_val, 4.0);
+
+        assert_eq!(trajectory.length(), 4.0);
+        assert!((trajectory.sample_by_distance(3.0).position.x_val - 3.0).abs() < 1e-9);
+        assert_eq!(trajectory.sample_by_distance(100.0).position.x_val, 4.0);
+    }
+
+    #[test]
+    fn unsorted_samples_are_rejected() {
+        assert!(Trajectory::new(vec![]).is_err());
+        assert!(Trajectory::new(vec![(1.0, pose(0.0, 0.0)), (0.0, pose(1.0, 0.0))]).is_err());
+    }
+
+    #[test]
+    fn velocities_are_finite_differences() {
+        let trajectory = Trajectory::new(vec![
+            (0.0, pose(0.0, 0.0)),
+            (1.0, pose(2.0, 0.5)),
+            (2.0, pose(4.0, 1.0)),
+        ])
+        .unwrap();
+
+        let samples = trajectory.with_velocities();
+        assert_eq!(samples.len(), 3);
+        for (_, linear, angular) in &samples {
+            assert!((linear.x_val - 2.0).abs() < 1e-9);
+            assert!((angular.z_val - 0.5).abs() < 1e-9);
+        }
+
+        let still = Trajectory::new(vec![(0.0, pose(1.0, 0.0))]).unwrap();
+        assert_eq!(still.with_velocities()[0].1.x_val, 0.0);
+    }
+}
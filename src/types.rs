@@ -8,7 +8,27 @@
 
 use fsds_rs_derive::FromIntoValue;
 use msgpack_rpc::Value;
-use std::ops::{Add, Div, DivAssign, Mul, MulAssign, Sub};
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::{Add, Deref, Div, DivAssign, Mul, MulAssign, Sub},
+    time::Instant,
+};
+
+/// Decode an integer tolerantly, `None` if `value` is not a number or does
+/// not fit `T`.
+///
+/// Both signed and unsigned `Value::Integer`s are accepted and finite
+/// `Value::F32`/`Value::F64` are rounded to the nearest integer, as for the
+/// integer fields of the `FromIntoValue` derive.
+pub(crate) fn integer_from_value<T: TryFrom<i128>>(value: &Value) -> Option<T> {
+    let number = match value {
+        Value::Integer(n) => n.as_i64().map(i128::from).or(n.as_u64().map(i128::from)),
+        Value::F32(f) if f.is_finite() => Some(f.round() as i128),
+        Value::F64(f) if f.is_finite() => Some(f.round() as i128),
+        _ => None,
+    };
+    number.and_then(|n| T::try_from(n).ok())
+}
 
 // ---------- //
 // IMAGE TYPE //
@@ -20,11 +40,13 @@ use std::ops::{Add, Div, DivAssign, Mul, MulAssign, Sub};
 /// currently supported:
 ///
 /// 0) Scene: an RGB image.
-/// 2) DepthVis: a depth image.
+/// 1) DepthPlanner: metric depth along the camera axis, see `DepthImage`.
+/// 2) DepthPerspective: metric distance from the camera, see `DepthImage`.
+/// 3) DepthVis: a depth image meant for visualization.
 ///
 /// Refer to the [FSDS API](https://fs-driverless.github.io/Formula-Student-Driverless-Simulator/v2.2.0/camera/#add-a-camera-to-the-car)
 /// and the [AirSim API](https://microsoft.github.io/AirSim/image_apis/#available-imagetype) for more information.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, FromIntoValue, Debug, PartialEq, Eq)]
 pub enum ImageType {
     Scene = 0,
     DepthPlanner = 1,
@@ -36,30 +58,23 @@ pub enum ImageType {
     Infrared = 7,
 }
 
-impl From<ImageType> for Value {
-    fn from(value: ImageType) -> Self {
-        Value::from(value as u64)
-    }
-}
-
-impl TryFrom<Value> for ImageType {
-    type Error = anyhow::Error;
-
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        match value {
-            // TODO: removeunwrap below
-            Value::Integer(value) => Ok(match value.as_u64().unwrap() {
-                0 => ImageType::Scene,
-                1 => ImageType::DepthPlanner,
-                2 => ImageType::DepthPerspective,
-                3 => ImageType::DepthVis,
-                4 => ImageType::DisparityNormalized,
-                5 => ImageType::Segmentation,
-                6 => ImageType::SurfaceNormals,
-                7 => ImageType::Infrared,
-                _ => return Err(anyhow::anyhow!("Invalid ImageType")),
-            }),
-            _ => Err(anyhow::anyhow!("Invalid ImageType")),
+impl ImageType {
+    /// Possible numbers of channels of an uncompressed image of this type.
+    ///
+    /// uint8 images are BGR, with an extra alpha channel on some simulator
+    /// versions. Float images have a single channel and are only produced
+    /// for depth and disparity types.
+    pub fn expected_channels(&self, pixels_as_float: bool) -> &'static [usize] {
+        match (self, pixels_as_float) {
+            (_, false) => &[3, 4],
+            (
+                ImageType::DepthPlanner
+                | ImageType::DepthPerspective
+                | ImageType::DepthVis
+                | ImageType::DisparityNormalized,
+                true,
+            ) => &[1],
+            (_, true) => &[],
         }
     }
 }
@@ -109,6 +124,36 @@ impl Vector3r {
         }
     }
 
+    /// The unit vector with the same direction, or the zero vector if the
+    /// length is zero.
+    pub fn normalize(&self) -> Self {
+        let length = self.get_length();
+        if length == 0.0 {
+            return Self::default();
+        }
+        *self / length
+    }
+
+    /// Component-wise (Hadamard) product, e.g. to apply per-axis scale
+    /// factors.
+    pub fn component_mul(&self, other: &Self) -> Self {
+        Self {
+            x_val: self.x_val * other.x_val,
+            y_val: self.y_val * other.y_val,
+            z_val: self.z_val * other.z_val,
+        }
+    }
+
+    /// Component-wise division, a zero component of `other` gives an infinite
+    /// or NaN component.
+    pub fn component_div(&self, other: &Self) -> Self {
+        Self {
+            x_val: self.x_val / other.x_val,
+            y_val: self.y_val / other.y_val,
+            z_val: self.z_val / other.z_val,
+        }
+    }
+
     /// Calculate the length of the vector.
     pub fn get_length(&self) -> f64 {
         (self.x_val.powi(2) + self.y_val.powi(2) + self.z_val.powi(2)).sqrt()
@@ -118,6 +163,90 @@ impl Vector3r {
     pub fn distance_to(&self, other: &Self) -> f64 {
         (*self - *other).get_length()
     }
+
+    /// Squared length of the vector, cheaper than `get_length` to compare
+    /// lengths.
+    pub fn length_squared(&self) -> f64 {
+        self.dot(self)
+    }
+
+    /// Squared distance between two vectors, cheaper than `distance_to` to
+    /// compare distances.
+    pub fn distance_squared_to(&self, other: &Self) -> f64 {
+        (*self - *other).length_squared()
+    }
+
+    /// Returns true if every component differs by at most `epsilon`.
+    pub fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        (self.x_val - other.x_val).abs() <= epsilon
+            && (self.y_val - other.y_val).abs() <= epsilon
+            && (self.z_val - other.z_val).abs() <= epsilon
+    }
+
+    /// Reflect the vector about the plane with the given normal.
+    ///
+    /// Computes `v - 2 * (v · n) * n`, e.g. the velocity after bouncing off
+    /// the surface described by `CollisionInfo::normal`. The normal is
+    /// normalized internally, so it does not need to be a unit vector. A zero
+    /// normal, e.g. that of a `CollisionInfo` without collision, leaves the
+    /// vector unchanged.
+    pub fn reflect(&self, normal: &Self) -> Self {
+        let n = normal.normalize();
+
+        let mut projection = n;
+        projection *= 2.0 * self.dot(&n);
+        *self - projection
+    }
+
+    /// Linear interpolation, `t = 0` gives `self` and `t = 1` gives `other`.
+    pub fn lerp(&self, other: &Self, t: f64) -> Self {
+        let mut delta = *other - *self;
+        delta *= t;
+        *self + delta
+    }
+
+    /// Spherical coordinates `(r, theta, phi)`: the length, the polar angle
+    /// from the z axis in `[0, pi]` and the azimuth from the x axis in
+    /// `(-pi, pi]`, in radians.
+    ///
+    /// The angles of the zero vector are 0.
+    pub fn to_spherical(&self) -> (f64, f64, f64) {
+        let r = self.get_length();
+        let theta = if r == 0.0 {
+            0.0
+        } else {
+            (self.z_val / r).clamp(-1.0, 1.0).acos()
+        };
+        (r, theta, self.y_val.atan2(self.x_val))
+    }
+
+    /// Inverse of `to_spherical`.
+    pub fn from_spherical(r: f64, theta: f64, phi: f64) -> Self {
+        Self {
+            x_val: r * theta.sin() * phi.cos(),
+            y_val: r * theta.sin() * phi.sin(),
+            z_val: r * theta.cos(),
+        }
+    }
+
+    /// Cylindrical coordinates `(rho, phi, z)`: the distance from the z axis,
+    /// the azimuth from the x axis in `(-pi, pi]` radians and the height.
+    pub fn to_cylindrical(&self) -> (f64, f64, f64) {
+        (
+            self.x_val.hypot(self.y_val),
+            self.y_val.atan2(self.x_val),
+            self.z_val,
+        )
+    }
+
+    /// Inverse of `to_cylindrical`.
+    pub fn from_cylindrical(rho: f64, phi: f64, z: f64) -> Self {
+        Self {
+            x_val: rho * phi.cos(),
+            y_val: rho * phi.sin(),
+            z_val: z,
+        }
+    }
 }
 
 impl Add for Vector3r {
@@ -160,6 +289,24 @@ impl MulAssign<f64> for Vector3r {
     }
 }
 
+impl Mul<f64> for Vector3r {
+    type Output = Self;
+
+    fn mul(mut self, other: f64) -> Self {
+        self *= other;
+        self
+    }
+}
+
+impl Div<f64> for Vector3r {
+    type Output = Self;
+
+    fn div(mut self, other: f64) -> Self {
+        self /= other;
+        self
+    }
+}
+
 // ----------- //
 // QUATERNIONR //
 // ----------- //
@@ -192,8 +339,16 @@ impl Quaternionr {
         }
     }
 
+    /// Returns true if every component differs by at most `epsilon`.
+    pub fn approx_eq(&self, other: &Self, epsilon: f64) -> bool {
+        (self.w_val - other.w_val).abs() <= epsilon
+            && (self.x_val - other.x_val).abs() <= epsilon
+            && (self.y_val - other.y_val).abs() <= epsilon
+            && (self.z_val - other.z_val).abs() <= epsilon
+    }
+
     /// The dot product of two quaternions.
-    /// 
+    ///
     /// The dot product of two quaternions is a scalar value that is the sum of
     /// the products of the corresponding components of the two quaternions.
     pub fn dot(&self, other: &Self) -> f64 {
@@ -204,7 +359,7 @@ impl Quaternionr {
     }
 
     /// Cross product of two quaternions.
-    /// 
+    ///
     /// Refer to the [Wikipedia page](https://en.wikipedia.org/wiki/Cross_product#Quaternions)
     /// for more information.
     pub fn cross(&self, other: &Self) -> Self {
@@ -229,6 +384,41 @@ impl Quaternionr {
         Err(anyhow::anyhow!("Quaternion is not normalized"))
     }
 
+    /// Rotation about the z axis, in radians in `(-pi, pi]`.
+    pub fn yaw(&self) -> f64 {
+        (2.0 * (self.w_val * self.z_val + self.x_val * self.y_val))
+            .atan2(1.0 - 2.0 * (self.y_val * self.y_val + self.z_val * self.z_val))
+    }
+
+    /// Build a unit quaternion from Euler angles in radians, applied in
+    /// yaw (z), pitch (y), roll (x) order as in AirSim.
+    pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> Self {
+        let (sr, cr) = (roll / 2.0).sin_cos();
+        let (sp, cp) = (pitch / 2.0).sin_cos();
+        let (sy, cy) = (yaw / 2.0).sin_cos();
+
+        Self {
+            w_val: cy * cr * cp + sy * sr * sp,
+            x_val: cy * sr * cp - sy * cr * sp,
+            y_val: cy * cr * sp + sy * sr * cp,
+            z_val: sy * cr * cp - cy * sr * sp,
+        }
+    }
+
+    /// Euler angles `(roll, pitch, yaw)` in radians, the inverse of
+    /// `from_euler`.
+    ///
+    /// Near a pitch of ±pi/2 (gimbal lock) roll and yaw are not unique, but
+    /// the result is still finite.
+    pub fn to_euler(&self) -> (f64, f64, f64) {
+        let (w, x, y, z) = (self.w_val, self.x_val, self.y_val, self.z_val);
+        let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+        // Rounding errors can push the argument slightly out of [-1, 1].
+        let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+
+        (roll, pitch, self.yaw())
+    }
+
     /// Conjugate of a quaternion.
     pub fn conjugate(&self) -> Self {
         Self {
@@ -240,7 +430,7 @@ impl Quaternionr {
     }
 
     /// Star of a quaternion.
-    /// 
+    ///
     /// Alias for the conjugate of a quaternion.
     pub fn star(&self) -> Self {
         self.conjugate()
@@ -263,6 +453,57 @@ impl Quaternionr {
     pub fn get_length(&self) -> f64 {
         (self.w_val.powi(2) + self.x_val.powi(2) + self.y_val.powi(2) + self.z_val.powi(2)).sqrt()
     }
+
+    /// Components in `[x, y, z, w]` order, as used by e.g. ROS, Eigen's
+    /// coefficients and Unity.
+    ///
+    /// The simulator and `Quaternionr` fields use `w, x, y, z`: use this and
+    /// `from_xyzw` at the boundary so the reorder is explicit.
+    pub fn to_xyzw(&self) -> [f64; 4] {
+        [self.x_val, self.y_val, self.z_val, self.w_val]
+    }
+
+    /// Build a quaternion from components in `[x, y, z, w]` order.
+    pub fn from_xyzw(xyzw: &[f64; 4]) -> Self {
+        Self {
+            w_val: xyzw[3],
+            x_val: xyzw[0],
+            y_val: xyzw[1],
+            z_val: xyzw[2],
+        }
+    }
+
+    /// Spherical linear interpolation between two rotations.
+    ///
+    /// `t = 0` gives `self` and `t = 1` gives `other`, following the shortest
+    /// arc. The inputs are expected to be unit quaternions and so is the
+    /// result.
+    pub fn slerp(&self, other: &Self, t: f64) -> Self {
+        let mut other = *other;
+        let mut cos = self.dot(&other);
+        // q and -q are the same rotation, take the shortest arc.
+        if cos < 0.0 {
+            other = Quaternionr::default() - other;
+            cos = -cos;
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly parallel: fall back to a normalized lerp.
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+
+        let result = Self {
+            w_val: a * self.w_val + b * other.w_val,
+            x_val: a * self.x_val + b * other.x_val,
+            y_val: a * self.y_val + b * other.y_val,
+            z_val: a * self.z_val + b * other.z_val,
+        };
+        result.sgn()
+    }
 }
 
 impl Add for Quaternionr {
@@ -339,10 +580,10 @@ impl From<Vector3r> for Quaternionr {
 /// ---- ///
 /// POSE ///
 /// ---- ///
-#[derive(Copy, Clone, Default, FromIntoValue)]
+#[derive(Copy, Clone, Default, FromIntoValue, Debug)]
 pub struct Pose {
-    position: Vector3r,
-    orientation: Quaternionr,
+    pub position: Vector3r,
+    pub orientation: Quaternionr,
 }
 
 impl Pose {
@@ -359,16 +600,41 @@ impl Pose {
             orientation: Quaternionr::nan_quaternionr(),
         }
     }
+
+    /// Interpolate between two poses: lerp of the position and slerp of the
+    /// orientation.
+    pub fn interpolate(&self, other: &Self, t: f64) -> Self {
+        Self {
+            position: self.position.lerp(&other.position, t),
+            orientation: self.orientation.slerp(&other.orientation, t),
+        }
+    }
+}
+
+/// Compact single-line representation meant for logging:
+/// `pos=(x,y,z) quat=(w,x,y,z)`.
+impl Display for Pose {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (p, q) = (self.position, self.orientation);
+        write!(
+            f,
+            "pos=({},{},{}) quat=({},{},{},{})",
+            p.x_val, p.y_val, p.z_val, q.w_val, q.x_val, q.y_val, q.z_val
+        )
+    }
 }
 
 /// --------- ///
 /// GEO POINT ///
 /// --------- ///
-#[derive(Copy, Clone, Default, FromIntoValue)]
+#[derive(Copy, Clone, Default, FromIntoValue, Debug)]
 pub struct GeoPoint {
-    latitude: f64,
-    longitude: f64,
-    altitude: f64,
+    /// Latitude in degrees.
+    pub latitude: f64,
+    /// Longitude in degrees.
+    pub longitude: f64,
+    /// Altitude in meters.
+    pub altitude: f64,
 }
 
 /// ------------- ///
@@ -396,31 +662,35 @@ impl Default for ImageRequest {
 /// -------------- ///
 /// IMAGE RESPONSE ///
 /// -------------- ///
-#[derive(FromIntoValue)]
+#[derive(Clone, Debug)]
 pub struct ImageResponse {
-    image_data_uint8: u64,
-    image_data_float: f64,
-    camera_position: Vector3r,
-    camera_orientation: Quaternionr,
-    timestamp: u64, // TODO: SystemTime?
-    message: String,
-    pixels_as_float: f64,
-    compress: bool,
-    width: u64,
-    height: u64,
-    image_type: ImageType,
+    /// Pixels (or PNG bytes if `compress` is set) when `pixels_as_float` is
+    /// false.
+    pub image_data_uint8: Vec<u8>,
+    /// Pixels when `pixels_as_float` is true.
+    pub image_data_float: Vec<f32>,
+    pub camera_position: Vector3r,
+    pub camera_orientation: Quaternionr,
+    /// Sim-time nanoseconds at which the image was captured.
+    pub time_stamp: u64,
+    pub message: String,
+    pub pixels_as_float: bool,
+    pub compress: bool,
+    pub width: u64,
+    pub height: u64,
+    pub image_type: ImageType,
 }
 
 impl Default for ImageResponse {
     fn default() -> Self {
         Self {
-            image_data_uint8: 0,
-            image_data_float: 0.0,
+            image_data_uint8: Vec::new(),
+            image_data_float: Vec::new(),
             camera_position: Default::default(),
             camera_orientation: Default::default(),
-            timestamp: 0,
+            time_stamp: 0,
             message: "".to_string(),
-            pixels_as_float: 0.0,
+            pixels_as_float: false,
             compress: true,
             width: 0,
             height: 0,
@@ -429,12 +699,278 @@ impl Default for ImageResponse {
     }
 }
 
+impl From<ImageResponse> for Value {
+    fn from(value: ImageResponse) -> Self {
+        Value::Map(vec![
+            ("image_data_uint8".into(), value.image_data_uint8.into()),
+            (
+                "image_data_float".into(),
+                Value::Array(
+                    value
+                        .image_data_float
+                        .into_iter()
+                        .map(Value::from)
+                        .collect(),
+                ),
+            ),
+            ("camera_position".into(), value.camera_position.into()),
+            ("camera_orientation".into(), value.camera_orientation.into()),
+            ("time_stamp".into(), value.time_stamp.into()),
+            ("message".into(), value.message.into()),
+            ("pixels_as_float".into(), value.pixels_as_float.into()),
+            ("compress".into(), value.compress.into()),
+            ("width".into(), value.width.into()),
+            ("height".into(), value.height.into()),
+            ("image_type".into(), value.image_type.into()),
+        ])
+    }
+}
+
+impl ImageResponse {
+    /// Uncompressed pixels in BGRA order, 4 bytes per pixel.
+    ///
+    /// FSDS, like AirSim, sends uncompressed scene images in BGR order, either
+    /// with 3 bytes per pixel or with an extra alpha byte depending on the
+    /// simulator version. Both layouts are accepted; when there is no alpha
+    /// channel it is set to 255.
+    pub fn to_bgra(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let channels = self.uncompressed_channels()?;
+
+        Ok(self
+            .image_data_uint8
+            .chunks_exact(channels)
+            .flat_map(|px| [px[0], px[1], px[2], if channels == 4 { px[3] } else { 255 }])
+            .collect())
+    }
+
+    /// Uncompressed pixels in RGBA order, 4 bytes per pixel.
+    ///
+    /// Same as `to_bgra` with the blue and red channels swapped.
+    pub fn to_rgba(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let mut pixels = self.to_bgra()?;
+        for px in pixels.chunks_exact_mut(4) {
+            px.swap(0, 2);
+        }
+        Ok(pixels)
+    }
+
+    /// Number of channels of the uncompressed pixel buffer.
+    ///
+    /// The length of `image_data_uint8` (or `image_data_float` if
+    /// `pixels_as_float` is set) is checked against
+    /// `width * height * channels` for each of the
+    /// `ImageType::expected_channels`, so that a truncated or mismatched
+    /// buffer is reported instead of being reshaped into garbage.
+    pub fn channels(&self) -> Result<usize, anyhow::Error> {
+        if self.compress {
+            return Err(anyhow::anyhow!(
+                "Image is compressed, request it with compress set to false to read its pixels"
+            ));
+        }
+
+        let len = if self.pixels_as_float {
+            self.image_data_float.len()
+        } else {
+            self.image_data_uint8.len()
+        };
+        let expected = self.image_type.expected_channels(self.pixels_as_float);
+        let pixels = self
+            .width
+            .checked_mul(self.height)
+            .and_then(|pixels| usize::try_from(pixels).ok())
+            .filter(|pixels| *pixels > 0);
+
+        pixels
+            .and_then(|pixels| {
+                expected
+                    .iter()
+                    .copied()
+                    .find(|channels| pixels.checked_mul(*channels) == Some(len))
+            })
+            .ok_or(anyhow::anyhow!(
+                "{:?} image data has {} values, expected {}x{} pixels with {:?} channels",
+                self.image_type,
+                len,
+                self.width,
+                self.height,
+                expected
+            ))
+    }
+
+    /// Number of bytes per pixel of an uncompressed `image_data_uint8`.
+    fn uncompressed_channels(&self) -> Result<usize, anyhow::Error> {
+        if self.compress || self.pixels_as_float {
+            return Err(anyhow::anyhow!(
+                "Image is not uncompressed uint8 data, request it with compress and pixels_as_float set to false"
+            ));
+        }
+
+        self.channels()
+    }
+}
+
+/// ----------- ///
+/// DEPTH IMAGE ///
+/// ----------- ///
+///
+/// What the values of a `DepthImage` measure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthKind {
+    /// Meters along the camera optical axis (z-buffer depth), from
+    /// `ImageType::DepthPlanner`.
+    Planar,
+    /// Meters along the ray from the camera center to the point, from
+    /// `ImageType::DepthPerspective`.
+    Perspective,
+    /// Normalized values meant for display, from `ImageType::DepthVis`. They
+    /// are not metric.
+    Visualization,
+}
+
+/// A float depth image, row-major.
+#[derive(Clone, Debug)]
+pub struct DepthImage {
+    pub width: u64,
+    pub height: u64,
+    pub kind: DepthKind,
+    pub depth: Vec<f32>,
+}
+
+impl DepthImage {
+    /// The depth of pixel `(x, y)`, `None` outside of the image.
+    pub fn at(&self, x: u64, y: u64) -> Option<f32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.depth.get((y * self.width + x) as usize).copied()
+    }
+}
+
+impl ImageResponse {
+    /// Decode a depth response.
+    ///
+    /// The image must have been requested with `pixels_as_float` set and
+    /// `compress` unset, with one of the depth image types.
+    pub fn to_depth(&self) -> Result<DepthImage, anyhow::Error> {
+        let kind = match self.image_type {
+            ImageType::DepthPlanner => DepthKind::Planar,
+            ImageType::DepthPerspective => DepthKind::Perspective,
+            ImageType::DepthVis => DepthKind::Visualization,
+            image_type => {
+                return Err(anyhow::anyhow!(
+                    "{:?} is not a depth image type",
+                    image_type
+                ))
+            }
+        };
+        if !self.pixels_as_float {
+            return Err(anyhow::anyhow!(
+                "Depth images must be requested with pixels_as_float set to true"
+            ));
+        }
+        self.channels()?;
+
+        Ok(DepthImage {
+            width: self.width,
+            height: self.height,
+            kind,
+            depth: self.image_data_float.clone(),
+        })
+    }
+}
+
+/// `ImageResponse` is converted by hand since the pixel buffers do not
+/// implement `TryFrom<Value>`.
+///
+/// Missing keys keep their default value. The `camera_name` sent by some
+/// AirSim versions is ignored, any other unknown key is an error as with the
+/// derive.
+impl TryFrom<Value> for ImageResponse {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let Value::Map(map) = value else {
+            return Err(anyhow::anyhow!(
+                "Value should be a Map to be converted to ImageResponse"
+            ));
+        };
+
+        let invalid = |field: &str, value: Value| {
+            anyhow::anyhow!("Invalid ImageResponse field {}: {}", field, value)
+        };
+
+        let mut response = ImageResponse::default();
+        for (key, value) in map {
+            let Some(key) = key.as_str() else {
+                return Err(anyhow::anyhow!(
+                    "ImageResponse keys should be strings, found {}",
+                    key
+                ));
+            };
+            match key {
+                "image_data_uint8" => {
+                    response.image_data_uint8 = match value {
+                        Value::Binary(bytes) => bytes,
+                        Value::Array(values) => values
+                            .into_iter()
+                            .map(|v| {
+                                v.as_u64()
+                                    .and_then(|b| u8::try_from(b).ok())
+                                    .ok_or_else(|| invalid(key, v))
+                            })
+                            .collect::<Result<_, _>>()?,
+                        value => return Err(invalid(key, value)),
+                    };
+                }
+                "image_data_float" => {
+                    let Value::Array(values) = value else {
+                        return Err(invalid(key, value));
+                    };
+                    response.image_data_float = values
+                        .into_iter()
+                        .map(|v| v.as_f64().map(|f| f as f32).ok_or_else(|| invalid(key, v)))
+                        .collect::<Result<_, _>>()?;
+                }
+                "camera_position" => response.camera_position = value.try_into()?,
+                "camera_orientation" => response.camera_orientation = value.try_into()?,
+                "time_stamp" => {
+                    response.time_stamp =
+                        integer_from_value(&value).ok_or_else(|| invalid(key, value))?
+                }
+                "message" => {
+                    response.message = String::try_from(value).map_err(|v| invalid(key, v))?
+                }
+                "pixels_as_float" => {
+                    response.pixels_as_float = bool::try_from(value).map_err(|v| invalid(key, v))?
+                }
+                "compress" => {
+                    response.compress = bool::try_from(value).map_err(|v| invalid(key, v))?
+                }
+                "width" => {
+                    response.width =
+                        integer_from_value(&value).ok_or_else(|| invalid(key, value))?
+                }
+                "height" => {
+                    response.height =
+                        integer_from_value(&value).ok_or_else(|| invalid(key, value))?
+                }
+                "image_type" => response.image_type = value.try_into()?,
+                "camera_name" => {}
+                _ => return Err(anyhow::anyhow!("Value::Map contains extra field: {}", key)),
+            }
+        }
+
+        Ok(response)
+    }
+}
+
 /// ------------ ///
 /// CAR CONTROLS ///
 /// ------------ ///
-#[derive(FromIntoValue)]
+#[derive(Clone, FromIntoValue, Debug, PartialEq)]
 pub struct CarControls {
     pub throttle: f64,
+    /// Normalized steering command in `[-1, 1]`, not the road-wheel angle.
     pub steering: f64,
     pub brake: f64,
     pub handbrake: bool,
@@ -457,23 +993,226 @@ impl Default for CarControls {
     }
 }
 
+impl CarControls {
+    /// Build controls from a normalized action vector.
+    ///
+    /// The action is `[steering, throttle_minus_brake]`, each in `[-1, 1]`.
+    /// Values outside that range are clamped. A positive second component is
+    /// applied as throttle, a negative one as brake. Missing components are
+    /// treated as `0.0` and extra components are ignored.
+    pub fn from_action(action: &[f64]) -> Self {
+        let steering = action.first().copied().unwrap_or(0.0).clamp(-1.0, 1.0);
+        let pedal = action.get(1).copied().unwrap_or(0.0).clamp(-1.0, 1.0);
+
+        Self {
+            steering,
+            throttle: pedal.max(0.0),
+            brake: (-pedal).max(0.0),
+            ..Default::default()
+        }
+    }
+
+    /// Interpolate from `self` to `target`, `t = 0` gives `self` and `t = 1`
+    /// gives `target`.
+    ///
+    /// Throttle, steering and brake are interpolated linearly, the other
+    /// fields are taken from `target` right away.
+    pub fn lerp(&self, target: &Self, t: f64) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        Self {
+            throttle: self.throttle + (target.throttle - self.throttle) * t,
+            steering: self.steering + (target.steering - self.steering) * t,
+            brake: self.brake + (target.brake - self.brake) * t,
+            ..target.clone()
+        }
+    }
+
+    /// The first argument of `setCarControls`, e.g. to log the exact message
+    /// sent to the simulator.
+    ///
+    /// It is a map with the keys `throttle`, `steering`, `brake` (floats),
+    /// `handbrake`, `is_manual_gear` (booleans), `manual_gear` (unsigned
+    /// integer) and `gear_immediate` (boolean), in this order. The second
+    /// argument is the vehicle name.
+    pub fn to_value(&self) -> Value {
+        self.clone().into()
+    }
+
+    /// Convert the controls to a normalized action vector.
+    ///
+    /// This is the inverse of `from_action`: the result is
+    /// `[steering, throttle - brake]`, clamped to `[-1, 1]`.
+    pub fn to_action(&self) -> Vec<f64> {
+        vec![
+            self.steering.clamp(-1.0, 1.0),
+            (self.throttle - self.brake).clamp(-1.0, 1.0),
+        ]
+    }
+}
+
+/// Sign of `CarControls::steering`, see
+/// `FSDSClient::set_steering_convention`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum SteeringConvention {
+    /// Positive steering turns right, as expected by the simulator.
+    #[default]
+    RightPositive,
+    /// Positive steering turns left, e.g. as in ROS (REP 103).
+    LeftPositive,
+}
+
+impl SteeringConvention {
+    /// Convert a steering command in this convention to the simulator one.
+    pub fn to_server(self, steering: f64) -> f64 {
+        match self {
+            SteeringConvention::RightPositive => steering,
+            SteeringConvention::LeftPositive => -steering,
+        }
+    }
+}
+
 /// ---------------- ///
 /// KINEMATICS STATE ///
 /// ---------------- ///
-#[derive(FromIntoValue, Default, Debug)]
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
 pub struct KinematicsState {
     pub position: Vector3r,
     pub orientation: Quaternionr,
     pub linear_velocity: Vector3r,
+    /// Body-frame angular velocity in rad/s.
     pub angular_velocity: Vector3r,
     pub linear_acceleration: Vector3r,
+    /// Body-frame angular acceleration in rad/s^2.
     pub angular_acceleration: Vector3r,
 }
 
+impl KinematicsState {
+    /// Number of columns of a telemetry row.
+    pub const ROW_LEN: usize = 19;
+
+    /// Flatten the state into a telemetry row, e.g. for CSV logging.
+    ///
+    /// The columns are, in order: position (x, y, z), orientation
+    /// (w, x, y, z), linear velocity, angular velocity, linear acceleration
+    /// and angular acceleration (x, y, z each). See `header` for the names.
+    pub fn to_row(&self) -> [f64; Self::ROW_LEN] {
+        let (p, q) = (self.position, self.orientation);
+        let (lv, av) = (self.linear_velocity, self.angular_velocity);
+        let (la, aa) = (self.linear_acceleration, self.angular_acceleration);
+
+        [
+            p.x_val, p.y_val, p.z_val, q.w_val, q.x_val, q.y_val, q.z_val, lv.x_val, lv.y_val,
+            lv.z_val, av.x_val, av.y_val, av.z_val, la.x_val, la.y_val, la.z_val, aa.x_val,
+            aa.y_val, aa.z_val,
+        ]
+    }
+
+    /// Heading of the car, the yaw of `orientation` in radians in
+    /// `(-pi, pi]`.
+    ///
+    /// In the NED frame of the simulator 0 is north (x) and the yaw grows
+    /// clockwise seen from above, towards east (y).
+    pub fn heading(&self) -> f64 {
+        self.orientation.yaw()
+    }
+
+    /// `heading` in degrees.
+    pub fn heading_deg(&self) -> f64 {
+        self.heading().to_degrees()
+    }
+
+    /// Like `to_row`, with every value rounded to `decimals` decimals.
+    ///
+    /// Rounded values print shorter, which keeps large CSV logs small at the
+    /// cost of precision.
+    pub fn to_row_rounded(&self, decimals: i32) -> [f64; Self::ROW_LEN] {
+        let scale = 10f64.powi(decimals);
+        self.to_row().map(|value| (value * scale).round() / scale)
+    }
+
+    /// Component-wise difference `self - other`, e.g. what changed since the
+    /// previous tick with `current.diff(&previous)`.
+    ///
+    /// The orientation is subtracted component by component too, so it is a
+    /// quick indicator of rotation rather than a rotation itself.
+    pub fn diff(&self, other: &Self) -> Self {
+        Self {
+            position: self.position - other.position,
+            orientation: self.orientation - other.orientation,
+            linear_velocity: self.linear_velocity - other.linear_velocity,
+            angular_velocity: self.angular_velocity - other.angular_velocity,
+            linear_acceleration: self.linear_acceleration - other.linear_acceleration,
+            angular_acceleration: self.angular_acceleration - other.angular_acceleration,
+        }
+    }
+
+    /// Column names matching `to_row`.
+    pub fn header() -> [&'static str; Self::ROW_LEN] {
+        [
+            "position_x",
+            "position_y",
+            "position_z",
+            "orientation_w",
+            "orientation_x",
+            "orientation_y",
+            "orientation_z",
+            "linear_velocity_x",
+            "linear_velocity_y",
+            "linear_velocity_z",
+            "angular_velocity_x",
+            "angular_velocity_y",
+            "angular_velocity_z",
+            "linear_acceleration_x",
+            "linear_acceleration_y",
+            "linear_acceleration_z",
+            "angular_acceleration_x",
+            "angular_acceleration_y",
+            "angular_acceleration_z",
+        ]
+    }
+}
+
+/// Compact single-line representation meant for logging, in the `to_row`
+/// order: `pos=(x,y,z) quat=(w,x,y,z) lin_vel=(x,y,z) ang_vel=(x,y,z)
+/// lin_acc=(x,y,z) ang_acc=(x,y,z)`.
+///
+/// Values are printed with full precision unless a precision is given, e.g.
+/// `format!("{:.3}", state)` prints 3 decimals.
+impl Display for KinematicsState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let number = |n: f64| match f.precision() {
+            Some(precision) => format!("{:.*}", precision, n),
+            None => n.to_string(),
+        };
+        let vector = |v: &Vector3r| {
+            format!(
+                "({},{},{})",
+                number(v.x_val),
+                number(v.y_val),
+                number(v.z_val)
+            )
+        };
+        let q = self.orientation;
+        write!(
+            f,
+            "pos={} quat=({},{},{},{}) lin_vel={} ang_vel={} lin_acc={} ang_acc={}",
+            vector(&self.position),
+            number(q.w_val),
+            number(q.x_val),
+            number(q.y_val),
+            number(q.z_val),
+            vector(&self.linear_velocity),
+            vector(&self.angular_velocity),
+            vector(&self.linear_acceleration),
+            vector(&self.angular_acceleration)
+        )
+    }
+}
+
 /// ----------------- ///
 /// ENVIRONMENT STATE ///
 /// ----------------- ///
-#[derive(FromIntoValue, Default)]
+#[derive(Clone, FromIntoValue, Default, Debug)]
 pub struct EnvironmentState {
     pub position: Vector3r,
     pub geo_point: GeoPoint,
@@ -486,53 +1225,1488 @@ pub struct EnvironmentState {
 /// -------------- ///
 /// COLLISION INFO ///
 /// -------------- ///
-#[derive(FromIntoValue)]
+#[derive(Clone, FromIntoValue, Default, Debug)]
 pub struct CollisionInfo {
     pub has_collided: bool,
     pub normal: Vector3r,
     pub impact_point: Vector3r,
     pub position: Vector3r,
     pub penetration_depth: f64,
-    pub timestamp: f64, // TODO: SystemTime?
+    /// Sim-time nanoseconds of the last collision.
+    pub time_stamp: u64,
     pub object_name: String,
-    pub object_id: u64,
+    /// Id of the object hit, `-1` if none.
+    pub object_id: i64,
 }
 
 /// --------- ///
 /// CAR STATE ///
 /// --------- ///
-#[derive(FromIntoValue)]
+///
+/// FSDS only reports speed, kinematics and timestamp: the physical road-wheel
+/// steering angle is not exposed by `getCarState` nor by any other RPC. The
+/// normalized `CarControls::steering` command is the only steering observable.
+/// Likewise, the applied handbrake and gear are not reported, see
+/// `FSDSClient::last_car_controls`.
+#[derive(FromIntoValue, Default, Debug)]
 pub struct CarState {
+    /// Forward speed in m/s.
     pub speed: f64,
     pub kinematics_estimated: KinematicsState,
-    pub timestamp: u64, // TODO: SystemTime?
+    /// Sim-time nanoseconds, scaled by the `ClockSpeed` setting.
+    pub timestamp: u64,
+}
+
+/// -------- ///
+/// IMU DATA ///
+/// -------- ///
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
+pub struct ImuData {
+    /// Sim-time nanoseconds of the measurement.
+    pub time_stamp: u64,
+    pub orientation: Quaternionr,
+    /// Body-frame angular velocity in rad/s.
+    pub angular_velocity: Vector3r,
+    /// Body-frame linear acceleration in m/s^2.
+    pub linear_acceleration: Vector3r,
+}
+
+/// -------- ///
+/// GPS DATA ///
+/// -------- ///
+/// Quality of a GNSS fix.
+#[derive(Clone, Copy, Default, FromIntoValue, Debug, PartialEq, Eq)]
+pub enum GnssFixType {
+    #[default]
+    NoFix = 0,
+    TimeOnly = 1,
+    Fix2d = 2,
+    Fix3d = 3,
+}
+
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
+pub struct GnssReport {
+    pub geo_point: GeoPoint,
+    /// Horizontal position accuracy, in meters.
+    pub eph: f64,
+    /// Vertical position accuracy, in meters.
+    pub epv: f64,
+    /// Velocity measured by the GNSS receiver, in m/s (north, east, down).
+    pub velocity: Vector3r,
+    pub fix_type: GnssFixType,
+    /// UTC time of the fix.
+    pub time_utc: u64,
+}
+
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
+pub struct GpsData {
+    /// Sim-time nanoseconds of the measurement.
+    pub time_stamp: u64,
+    pub gnss: GnssReport,
+    pub is_valid: bool,
+}
+
+impl GpsData {
+    /// Horizontal speed from the GNSS velocity, in m/s.
+    ///
+    /// This is what a GPS-only stack would measure, noisier than the ground
+    /// truth kinematics velocity. The vertical component is ignored.
+    pub fn ground_speed(&self) -> f64 {
+        let velocity = self.gnss.velocity;
+        velocity.x_val.hypot(velocity.y_val)
+    }
+
+    /// Latitude and longitude in degrees and altitude in meters.
+    pub fn lat_lon_alt(&self) -> (f64, f64, f64) {
+        let point = self.gnss.geo_point;
+        (point.latitude, point.longitude, point.altitude)
+    }
+}
+
+/// ---------- ///
+/// LIDAR DATA ///
+/// ---------- ///
+#[derive(Clone, Default, Debug)]
+pub struct LidarData {
+    /// Flattened `x, y, z` coordinates of the points, in the lidar frame.
+    pub point_cloud: Vec<f64>,
+    /// Sim-time nanoseconds of the scan.
+    pub time_stamp: u64,
+    /// Pose of the lidar.
+    pub pose: Pose,
+}
+
+impl LidarData {
+    /// The points of the scan, in the lidar frame.
+    ///
+    /// A scan without any hit, e.g. towards open sky, is empty. Some servers
+    /// then send a single `0.0` instead of an empty array: trailing values
+    /// which do not form a whole point are ignored, so both yield no points.
+    pub fn points(&self) -> Vec<Vector3r> {
+        self.point_cloud
+            .chunks_exact(3)
+            .map(|point| Vector3r {
+                x_val: point[0],
+                y_val: point[1],
+                z_val: point[2],
+            })
+            .collect()
+    }
+
+    /// Returns true if the scan has no points.
+    pub fn is_empty(&self) -> bool {
+        self.point_cloud.len() < 3
+    }
+}
+
+impl From<LidarData> for Value {
+    fn from(value: LidarData) -> Self {
+        Value::Map(vec![
+            (
+                "point_cloud".into(),
+                Value::Array(value.point_cloud.into_iter().map(Value::from).collect()),
+            ),
+            ("time_stamp".into(), value.time_stamp.into()),
+            ("pose".into(), value.pose.into()),
+        ])
+    }
+}
+
+/// `LidarData` is converted by hand since `Vec<f64>` does not implement
+/// `TryFrom<Value>`.
+impl TryFrom<Value> for LidarData {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let Value::Map(map) = value else {
+            return Err(anyhow::anyhow!(
+                "Value should be a Map to be converted to LidarData"
+            ));
+        };
+
+        let mut data = LidarData::default();
+        for (key, value) in map {
+            match key.as_str() {
+                Some("point_cloud") => {
+                    let Value::Array(points) = value else {
+                        return Err(anyhow::anyhow!("LidarData point_cloud should be an Array"));
+                    };
+                    data.point_cloud = points
+                        .into_iter()
+                        .map(|v| v.as_f64().ok_or(anyhow::anyhow!("Invalid point: {}", v)))
+                        .collect::<Result<_, _>>()?;
+                }
+                Some("time_stamp") => {
+                    data.time_stamp = integer_from_value(&value)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid time_stamp: {}", value))?;
+                }
+                Some("pose") => data.pose = value.try_into()?,
+                _ => return Err(anyhow::anyhow!("Value::Map contains extra field: {}", key)),
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// ------------------------- ///
+/// GROUND SPEED SENSOR DATA ///
+/// ------------------------- ///
+///
+/// The ground speed sensor (GSS) only measures velocity, so it is not decoded
+/// into a mostly empty `KinematicsState`. The payload matches the
+/// `GroundSpeedSensorData` of the FSDS Python client.
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
+pub struct GroundSpeedSensorData {
+    /// Sim-time nanoseconds of the measurement.
+    pub time_stamp: u64,
+    /// Velocity in m/s, in the vehicle frame.
+    pub linear_velocity: Vector3r,
+    /// Angular velocity in rad/s, in the vehicle frame. Zero if the server
+    /// does not send it.
+    #[fsds(default)]
+    pub angular_velocity: Vector3r,
+}
+
+impl GroundSpeedSensorData {
+    /// Speed in m/s, the length of `linear_velocity`.
+    pub fn speed(&self) -> f64 {
+        self.linear_velocity.get_length()
+    }
 }
 
 /// ----------- ///
 /// POSITION 2D ///
 /// ----------- ///
-#[derive(FromIntoValue, Default)]
+#[derive(Copy, Clone, FromIntoValue, Default, Debug)]
 pub struct Position2D {
     pub x_val: f64,
     pub y_val: f64,
 }
 
-/// ------------- ///
-/// REFEREE STATE ///
-/// ------------- ///
-#[derive(Default)]
-pub struct RefereeState {
-    pub doo_counter: u64,
-    pub laps: f64,
+impl Position2D {
+    /// Convert a referee map to a `Position2D`.
+    ///
+    /// The referee sends points as `{x, y, ...}` maps (cones also carry a
+    /// `color`), so both `x`/`y` and `x_val`/`y_val` keys are accepted and
+    /// `color` is ignored. Any other key is an error.
+    fn from_referee_value(value: Value) -> Result<Self, anyhow::Error> {
+        let Value::Map(map) = value else {
+            return Err(anyhow::anyhow!(
+                "Value should be a Map to be converted to Position2D"
+            ));
+        };
+
+        let mut x_val = None;
+        let mut y_val = None;
+        for (key, value) in map {
+            let field = match key.as_str() {
+                Some("x") | Some("x_val") => &mut x_val,
+                Some("y") | Some("y_val") => &mut y_val,
+                Some("color") => continue,
+                _ => return Err(anyhow::anyhow!("Value::Map contains extra field: {}", key)),
+            };
+            *field = Some(
+                f64::try_from(value)
+                    .map_err(|v| anyhow::anyhow!("Invalid Position2D coordinate: {}", v))?,
+            );
+        }
+
+        Ok(Self {
+            x_val: x_val.ok_or(anyhow::anyhow!("Field x not found in Value::Map."))?,
+            y_val: y_val.ok_or(anyhow::anyhow!("Field y not found in Value::Map."))?,
+        })
+    }
+}
+
+/// ------------- ///
+/// REFEREE STATE ///
+/// ------------- ///
+#[derive(Clone, Default, Debug)]
+pub struct RefereeState {
+    pub doo_counter: u64,
+    pub laps: f64,
     pub initial_position: Position2D,
-    pub cones: Vec<Position2D>, // TODO: Vec<Position2D> does not implement Into<Value>
+    pub cones: Vec<Position2D>,
+}
+
+/// `RefereeState` is converted by hand since `Vec<Position2D>` does not
+/// implement `TryFrom<Value>`.
+///
+/// `laps` is accepted either as a number or as the array of lap times, in
+/// which case the number of completed laps is used.
+impl TryFrom<Value> for RefereeState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let Value::Map(map) = value else {
+            return Err(anyhow::anyhow!(
+                "Value should be a Map to be converted to RefereeState"
+            ));
+        };
+
+        let mut state = RefereeState::default();
+        for (key, value) in map {
+            match key.as_str() {
+                Some("doo_counter") => {
+                    state.doo_counter = integer_from_value(&value)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid doo_counter: {}", value))?;
+                }
+                Some("laps") => {
+                    state.laps = match value {
+                        Value::Array(laps) => laps.len() as f64,
+                        value => f64::try_from(value)
+                            .map_err(|v| anyhow::anyhow!("Invalid laps: {}", v))?,
+                    };
+                }
+                Some("initial_position") => {
+                    state.initial_position = Position2D::from_referee_value(value)?;
+                }
+                Some("cones") => {
+                    let Value::Array(cones) = value else {
+                        return Err(anyhow::anyhow!("RefereeState cones should be an Array"));
+                    };
+                    state.cones = cones
+                        .into_iter()
+                        .map(Position2D::from_referee_value)
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => return Err(anyhow::anyhow!("Value::Map contains extra field: {}", key)),
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+/// ------------------------------ ///
+/// GROUND TRUTH AND SENSED VALUES ///
+/// ------------------------------ ///
+///
+/// Marks a value read from the simulator state, which a real car cannot know,
+/// e.g. the kinematics returned by `FSDSClient::ground_truth_kinematics`.
+///
+/// Localization code should take `Sensed` inputs so that ground truth cannot
+/// be passed by mistake where a sensor estimate is expected. The inner value
+/// is available through `Deref` or `into_inner`.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct GroundTruth<T>(T);
+
+impl<T> GroundTruth<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for GroundTruth<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Marks a value measured by a simulated sensor (IMU, GPS, GSS, lidar...),
+/// with the noise a real car would see. See `GroundTruth`.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct Sensed<T>(T);
+
+impl<T> Sensed<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Sensed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// ------------- ///
+/// LABELED FRAME ///
+/// ------------- ///
+///
+/// What `FSDSClient::capture_labeled_frame` captures.
+#[derive(Clone, Debug)]
+pub struct CaptureConfig {
+    pub vehicle_name: String,
+    pub camera_name: String,
+    /// Pause the simulation during the capture, so that the images, the
+    /// kinematics and the referee state describe exactly the same instant.
+    pub pause: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            vehicle_name: "FSCar".to_string(),
+            camera_name: "0".to_string(),
+            pause: true,
+        }
+    }
+}
+
+/// A dataset sample: synchronized images with their ground truth labels.
+#[derive(Clone, Debug)]
+pub struct LabeledFrame {
+    /// Sim-time nanoseconds of the capture, from the scene image.
+    pub time_stamp: u64,
+    /// Uncompressed scene image, see `ImageResponse::to_rgba`.
+    pub scene: ImageResponse,
+    /// Perspective depth in meters.
+    pub depth: DepthImage,
+    /// Uncompressed segmentation image.
+    pub segmentation: ImageResponse,
+    /// Ground truth kinematics of the vehicle.
+    pub kinematics: KinematicsState,
+    /// Ground truth cone positions, in the referee frame.
+    pub cones: Vec<Position2D>,
+}
+
+/// Everything the simulator knows about a vehicle at one instant, see
+/// `FSDSClient::sim_get_ground_truth_snapshot`.
+#[derive(Clone, Debug)]
+pub struct GroundTruthSnapshot {
+    /// When the requests were sent, shared by every part of the snapshot.
+    pub captured_at: Instant,
+    pub kinematics: KinematicsState,
+    pub environment: EnvironmentState,
+    pub collision: CollisionInfo,
+    pub referee: RefereeState,
+}
+
+/// ----------------- ///
+/// COORDINATE FRAMES ///
+/// ----------------- ///
+///
+/// Frame of the data returned by the typed getters, see
+/// `FSDSClient::set_output_frame`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// North-East-Down world frame with Forward-Right-Down body axes, as sent
+    /// by the simulator.
+    #[default]
+    Ned,
+    /// East-North-Up world frame with Forward-Left-Up body axes, as used by
+    /// ROS.
+    Enu,
+}
+
+/// Data which can be expressed in another `Frame`.
+///
+/// World-frame vectors and poses are converted from NED to ENU, body-frame
+/// vectors (IMU and GSS readings, kinematics angular rates) from FRD to FLU.
+pub trait FrameConvert: Sized {
+    /// Convert from NED/FRD to ENU/FLU. The conversion is its own inverse.
+    fn ned_to_enu(&self) -> Self;
+
+    /// Express data received from the simulator in `frame`.
+    fn to_frame(self, frame: Frame) -> Self {
+        match frame {
+            Frame::Ned => self,
+            Frame::Enu => self.ned_to_enu(),
+        }
+    }
+}
+
+impl Vector3r {
+    /// Convert a body-frame vector from FRD to FLU, or back.
+    pub fn frd_to_flu(&self) -> Self {
+        Self {
+            x_val: self.x_val,
+            y_val: -self.y_val,
+            z_val: -self.z_val,
+        }
+    }
+}
+
+impl FrameConvert for Vector3r {
+    fn ned_to_enu(&self) -> Self {
+        Self {
+            x_val: self.y_val,
+            y_val: self.x_val,
+            z_val: -self.z_val,
+        }
+    }
+}
+
+impl FrameConvert for Quaternionr {
+    /// Convert an orientation of FRD body axes in NED to FLU body axes in ENU.
+    fn ned_to_enu(&self) -> Self {
+        // Axes swap of the world frame, then a quarter turn about the up axis
+        // so that a car facing north keeps facing north.
+        let swapped = Quaternionr {
+            w_val: self.w_val,
+            x_val: self.y_val,
+            y_val: self.x_val,
+            z_val: -self.z_val,
+        };
+        let quarter_turn = Quaternionr {
+            w_val: std::f64::consts::FRAC_1_SQRT_2,
+            x_val: 0.0,
+            y_val: 0.0,
+            z_val: std::f64::consts::FRAC_1_SQRT_2,
+        };
+        swapped * quarter_turn
+    }
+}
+
+impl FrameConvert for Pose {
+    fn ned_to_enu(&self) -> Self {
+        Pose {
+            position: self.position.ned_to_enu(),
+            orientation: self.orientation.ned_to_enu(),
+        }
+    }
+}
+
+impl FrameConvert for KinematicsState {
+    fn ned_to_enu(&self) -> Self {
+        KinematicsState {
+            position: self.position.ned_to_enu(),
+            orientation: self.orientation.ned_to_enu(),
+            linear_velocity: self.linear_velocity.ned_to_enu(),
+            angular_velocity: self.angular_velocity.frd_to_flu(),
+            linear_acceleration: self.linear_acceleration.ned_to_enu(),
+            angular_acceleration: self.angular_acceleration.frd_to_flu(),
+        }
+    }
+}
+
+impl FrameConvert for CameraInfo {
+    fn ned_to_enu(&self) -> Self {
+        CameraInfo {
+            pose: self.pose.ned_to_enu(),
+            ..self.clone()
+        }
+    }
+}
+
+impl FrameConvert for CarState {
+    fn ned_to_enu(&self) -> Self {
+        CarState {
+            kinematics_estimated: self.kinematics_estimated.ned_to_enu(),
+            ..*self
+        }
+    }
+}
+
+impl FrameConvert for EnvironmentState {
+    fn ned_to_enu(&self) -> Self {
+        EnvironmentState {
+            position: self.position.ned_to_enu(),
+            gravity: self.gravity.ned_to_enu(),
+            ..self.clone()
+        }
+    }
+}
+
+impl FrameConvert for CollisionInfo {
+    fn ned_to_enu(&self) -> Self {
+        CollisionInfo {
+            normal: self.normal.ned_to_enu(),
+            impact_point: self.impact_point.ned_to_enu(),
+            position: self.position.ned_to_enu(),
+            ..self.clone()
+        }
+    }
+}
+
+impl FrameConvert for ImuData {
+    fn ned_to_enu(&self) -> Self {
+        ImuData {
+            orientation: self.orientation.ned_to_enu(),
+            angular_velocity: self.angular_velocity.frd_to_flu(),
+            linear_acceleration: self.linear_acceleration.frd_to_flu(),
+            ..*self
+        }
+    }
+}
+
+impl FrameConvert for GpsData {
+    fn ned_to_enu(&self) -> Self {
+        let mut gps = *self;
+        gps.gnss.velocity = gps.gnss.velocity.ned_to_enu();
+        gps
+    }
+}
+
+impl FrameConvert for GroundSpeedSensorData {
+    fn ned_to_enu(&self) -> Self {
+        GroundSpeedSensorData {
+            linear_velocity: self.linear_velocity.frd_to_flu(),
+            angular_velocity: self.angular_velocity.frd_to_flu(),
+            ..*self
+        }
+    }
+}
+
+/// Only the pose of the lidar is converted, the points stay in the lidar
+/// frame.
+impl FrameConvert for LidarData {
+    fn ned_to_enu(&self) -> Self {
+        LidarData {
+            pose: self.pose.ned_to_enu(),
+            ..self.clone()
+        }
+    }
+}
+
+/// -------- ///
+/// NALGEBRA ///
+/// -------- ///
+///
+/// Conversions to and from `nalgebra`, behind the `nalgebra` feature. They
+/// keep the simulator frame (NED, meters), nothing is flipped.
+#[cfg(feature = "nalgebra")]
+impl From<Vector3r> for nalgebra::Vector3<f64> {
+    fn from(value: Vector3r) -> Self {
+        nalgebra::Vector3::new(value.x_val, value.y_val, value.z_val)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Vector3<f64>> for Vector3r {
+    fn from(value: nalgebra::Vector3<f64>) -> Self {
+        Self {
+            x_val: value.x,
+            y_val: value.y,
+            z_val: value.z,
+        }
+    }
+}
+
+/// The quaternion is normalized, the simulator only sends unit quaternions.
+#[cfg(feature = "nalgebra")]
+impl From<Quaternionr> for nalgebra::UnitQuaternion<f64> {
+    fn from(value: Quaternionr) -> Self {
+        nalgebra::UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(
+            value.w_val,
+            value.x_val,
+            value.y_val,
+            value.z_val,
+        ))
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::UnitQuaternion<f64>> for Quaternionr {
+    fn from(value: nalgebra::UnitQuaternion<f64>) -> Self {
+        Self {
+            w_val: value.w,
+            x_val: value.i,
+            y_val: value.j,
+            z_val: value.k,
+        }
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<Pose> for nalgebra::Isometry3<f64> {
+    fn from(value: Pose) -> Self {
+        nalgebra::Isometry3::from_parts(
+            nalgebra::Translation3::from(nalgebra::Vector3::from(value.position)),
+            value.orientation.into(),
+        )
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Isometry3<f64>> for Pose {
+    fn from(value: nalgebra::Isometry3<f64>) -> Self {
+        Self {
+            position: value.translation.vector.into(),
+            orientation: value.rotation.into(),
+        }
+    }
+}
+
+/// The pose part of the state.
+#[cfg(feature = "nalgebra")]
+impl From<&KinematicsState> for nalgebra::Isometry3<f64> {
+    fn from(value: &KinematicsState) -> Self {
+        Pose::new(Some(value.position), Some(value.orientation)).into()
+    }
+}
+
+/// The velocity twist `[linear; angular]`: linear velocity in m/s followed
+/// by angular velocity in rad/s.
+#[cfg(feature = "nalgebra")]
+impl From<&KinematicsState> for nalgebra::Vector6<f64> {
+    fn from(value: &KinematicsState) -> Self {
+        let (v, w) = (value.linear_velocity, value.angular_velocity);
+        nalgebra::Vector6::new(v.x_val, v.y_val, v.z_val, w.x_val, w.y_val, w.z_val)
+    }
 }
 
-// TODO:
-// ----------------- ///
-// PROJECTION MATRIX ///
-// ----------------- ///
-// #[derive(FromIntoValue, Default)]
-// pub struct ProjectionMatrix {
-//     pub matrix: Vec<_>,
-// }
+/// ----------------- ///
+/// PROJECTION MATRIX ///
+/// ----------------- ///
+///
+/// Row-major 4x4 projection matrix of a camera.
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct ProjectionMatrix {
+    pub matrix: [[f64; 4]; 4],
+}
+
+impl From<ProjectionMatrix> for Value {
+    fn from(value: ProjectionMatrix) -> Self {
+        let rows = value
+            .matrix
+            .iter()
+            .map(|row| Value::Array(row.iter().map(|&v| v.into()).collect()))
+            .collect();
+        Value::Map(vec![("matrix".into(), Value::Array(rows))])
+    }
+}
+
+/// `ProjectionMatrix` is converted by hand since arrays do not implement
+/// `TryFrom<Value>`.
+impl TryFrom<Value> for ProjectionMatrix {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let Value::Map(map) = value else {
+            return Err(anyhow::anyhow!(
+                "Value should be a Map to be converted to ProjectionMatrix"
+            ));
+        };
+        let [(key, Value::Array(rows))] = map.as_slice() else {
+            return Err(anyhow::anyhow!(
+                "ProjectionMatrix should only contain a matrix Array"
+            ));
+        };
+        if key.as_str() != Some("matrix") || rows.len() != 4 {
+            return Err(anyhow::anyhow!("ProjectionMatrix should be 4x4"));
+        }
+
+        let mut matrix = [[0.0; 4]; 4];
+        for (row, values) in matrix.iter_mut().zip(rows) {
+            let values = values
+                .as_array()
+                .filter(|values| values.len() == 4)
+                .ok_or(anyhow::anyhow!("ProjectionMatrix should be 4x4"))?;
+            for (cell, value) in row.iter_mut().zip(values) {
+                *cell = value
+                    .as_f64()
+                    .ok_or(anyhow::anyhow!("Invalid ProjectionMatrix entry: {}", value))?;
+            }
+        }
+
+        Ok(ProjectionMatrix { matrix })
+    }
+}
+
+/// ----------- ///
+/// CAMERA INFO ///
+/// ----------- ///
+#[derive(Clone, FromIntoValue, Default, Debug)]
+pub struct CameraInfo {
+    /// Pose of the camera in the world frame.
+    pub pose: Pose,
+    /// Horizontal field of view in degrees.
+    pub fov: f64,
+    pub proj_mat: ProjectionMatrix,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn car_controls_action_round_trip() {
+        let controls = CarControls::from_action(&[-0.5, -0.25]);
+        assert_eq!(controls.steering, -0.5);
+        assert_eq!(controls.throttle, 0.0);
+        assert_eq!(controls.brake, 0.25);
+        assert_eq!(controls.to_action(), vec![-0.5, -0.25]);
+
+        let clamped = CarControls::from_action(&[3.0, 2.0]);
+        assert_eq!(clamped.to_action(), vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn car_controls_value_round_trip() {
+        let controls = CarControls {
+            throttle: 0.75,
+            steering: -0.5,
+            brake: 0.125,
+            handbrake: true,
+            is_manual_gear: true,
+            manual_gear: 3,
+            gear_immediate: false,
+        };
+
+        let value = Value::from(controls.clone());
+        assert_eq!(value["handbrake"], Value::Boolean(true));
+        assert_eq!(value["manual_gear"], Value::from(3u64));
+        assert_eq!(CarControls::try_from(value).unwrap(), controls);
+
+        let defaults = CarControls::default();
+        assert_eq!(
+            CarControls::try_from(Value::from(defaults.clone())).unwrap(),
+            defaults
+        );
+    }
+
+    #[test]
+    fn euler_round_trip() {
+        use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+        for (roll, pitch, yaw) in [
+            (0.0, 0.0, 0.0),
+            (0.1, -0.2, 0.3),
+            (-FRAC_PI_4, 0.5, PI - 0.01),
+            (0.3, -1.2, -2.0),
+        ] {
+            let q = Quaternionr::from_euler(roll, pitch, yaw);
+            assert!((q.get_length() - 1.0).abs() < 1e-12);
+            let (r, p, y) = q.to_euler();
+            assert!((r - roll).abs() < 1e-9);
+            assert!((p - pitch).abs() < 1e-9);
+            assert!((y - yaw).abs() < 1e-9);
+        }
+
+        // A pure yaw matches the yaw of a z rotation.
+        let q = Quaternionr::from_euler(0.0, 0.0, 1.0);
+        assert!((q.z_val - 0.5f64.sin()).abs() < 1e-12);
+
+        // Gimbal lock does not produce NaNs.
+        let (roll, pitch, yaw) = Quaternionr::from_euler(0.2, FRAC_PI_2, 0.4).to_euler();
+        assert!(roll.is_finite() && yaw.is_finite());
+        assert!((pitch - FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn car_controls_wire_format() {
+        let controls = CarControls {
+            throttle: 0.5,
+            steering: -0.25,
+            manual_gear: 2,
+            ..Default::default()
+        };
+
+        let expected = Value::Map(vec![
+            ("throttle".into(), Value::F64(0.5)),
+            ("steering".into(), Value::F64(-0.25)),
+            ("brake".into(), Value::F64(0.0)),
+            ("handbrake".into(), Value::Boolean(false)),
+            ("is_manual_gear".into(), Value::Boolean(false)),
+            ("manual_gear".into(), Value::from(2u64)),
+            ("gear_immediate".into(), Value::Boolean(true)),
+        ]);
+        assert_eq!(controls.to_value(), expected);
+    }
+
+    #[test]
+    fn integer_fields_accept_floats() {
+        let mut value: Value = CarState {
+            speed: 1.0,
+            kinematics_estimated: KinematicsState::default(),
+            timestamp: 0,
+        }
+        .into();
+        if let Value::Map(map) = &mut value {
+            map[2].1 = Value::F64(42.4);
+        }
+
+        let state = CarState::try_from(value).unwrap();
+        assert_eq!(state.timestamp, 42);
+    }
+
+    #[test]
+    fn vector3r_reflect() {
+        let v = Vector3r {
+            x_val: 1.0,
+            y_val: -2.0,
+            z_val: 0.0,
+        };
+        let normal = Vector3r {
+            x_val: 0.0,
+            y_val: 3.0,
+            z_val: 0.0,
+        };
+
+        let expected = Vector3r {
+            x_val: 1.0,
+            y_val: 2.0,
+            z_val: 0.0,
+        };
+        crate::assert_vec_approx_eq!(v.reflect(&normal), expected);
+    }
+
+    #[test]
+    fn vector3r_reflect_zero_normal() {
+        let v = Vector3r {
+            x_val: 1.0,
+            y_val: -2.0,
+            z_val: 0.5,
+        };
+        let normal = CollisionInfo::default().normal;
+
+        crate::assert_vec_approx_eq!(v.reflect(&normal), v);
+    }
+
+    #[test]
+    fn imu_data_value_round_trip() {
+        let vector = |x: f64, y: f64, z: f64| {
+            Value::Map(vec![
+                ("x_val".into(), x.into()),
+                ("y_val".into(), y.into()),
+                ("z_val".into(), z.into()),
+            ])
+        };
+        // Not normalized, as sent on the first tick.
+        let orientation = Value::Map(vec![
+            ("w_val".into(), 2.0.into()),
+            ("x_val".into(), 0.0.into()),
+            ("y_val".into(), 0.0.into()),
+            ("z_val".into(), 0.0.into()),
+        ]);
+        let value = Value::Map(vec![
+            ("time_stamp".into(), 42u64.into()),
+            ("orientation".into(), orientation),
+            ("angular_velocity".into(), vector(0.0, 0.0, 0.5)),
+            ("linear_acceleration".into(), vector(1.0, 0.0, -9.81)),
+        ]);
+
+        let imu = ImuData::try_from(value.clone()).unwrap();
+        assert_eq!(imu.time_stamp, 42);
+        assert_eq!(imu.orientation.w_val, 2.0);
+        assert_eq!(imu.angular_velocity.z_val, 0.5);
+        assert_eq!(imu.linear_acceleration.z_val, -9.81);
+        assert_eq!(Value::from(imu), value);
+    }
+
+    #[test]
+    fn derived_enums_use_the_discriminant() {
+        assert_eq!(Value::from(ImageType::Segmentation), Value::from(5u64));
+        assert_eq!(Value::from(GnssFixType::Fix2d), Value::from(2u64));
+        for image_type in [ImageType::Scene, ImageType::Infrared] {
+            assert_eq!(
+                ImageType::try_from(Value::from(image_type)).unwrap(),
+                image_type
+            );
+        }
+        assert!(ImageType::try_from(Value::from(8u64)).is_err());
+    }
+
+    #[test]
+    fn image_type_accepts_floats() {
+        assert_eq!(
+            ImageType::try_from(Value::F64(3.0)).unwrap(),
+            ImageType::DepthVis
+        );
+        assert_eq!(
+            ImageType::try_from(Value::F32(5.0)).unwrap(),
+            ImageType::Segmentation
+        );
+        assert_eq!(
+            ImageType::try_from(Value::F64(1.9999)).unwrap(),
+            ImageType::DepthPerspective
+        );
+        assert!(ImageType::try_from(Value::F64(f64::NAN)).is_err());
+        assert!(ImageType::try_from(Value::from(-1)).is_err());
+        assert!(ImageType::try_from(Value::from("Scene")).is_err());
+    }
+
+    #[test]
+    fn camera_info_round_trip() {
+        let mut info = CameraInfo {
+            pose: Pose::new(Some(Vector3r::default()), Some(Quaternionr::default())),
+            fov: 90.0,
+            ..Default::default()
+        };
+        info.proj_mat.matrix[0] = [1.0, 0.0, 0.0, 0.5];
+        info.proj_mat.matrix[3][2] = -1.0;
+
+        let decoded = CameraInfo::try_from(Value::from(info.clone())).unwrap();
+        assert_eq!(decoded.fov, 90.0);
+        assert_eq!(decoded.proj_mat, info.proj_mat);
+
+        let short = Value::Map(vec![(
+            "matrix".into(),
+            Value::Array(vec![Value::Array(vec![])]),
+        )]);
+        assert!(ProjectionMatrix::try_from(short).is_err());
+    }
+
+    #[test]
+    fn squared_lengths_match_sqrt_versions() {
+        let a = Vector3r {
+            x_val: 1.0,
+            y_val: -2.0,
+            z_val: 2.0,
+        };
+        let b = Vector3r {
+            x_val: 0.5,
+            y_val: 4.0,
+            z_val: -1.0,
+        };
+
+        assert_eq!(a.length_squared(), 9.0);
+        assert!((a.length_squared() - a.get_length().powi(2)).abs() < 1e-12);
+        assert!((a.distance_squared_to(&b) - a.distance_to(&b).powi(2)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn scalar_ops_and_normalize() {
+        let v = Vector3r {
+            x_val: 3.0,
+            y_val: 0.0,
+            z_val: -4.0,
+        };
+
+        let doubled = v * 2.0;
+        assert_eq!((doubled.x_val, doubled.z_val), (6.0, -8.0));
+        crate::assert_vec_approx_eq!(doubled / 2.0, v);
+
+        let unit = v.normalize();
+        assert!((unit.get_length() - 1.0).abs() < 1e-12);
+        assert_eq!(unit.x_val, 0.6);
+        let zero = Vector3r::default().normalize();
+        assert_eq!((zero.x_val, zero.y_val, zero.z_val), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn component_wise_mul_and_div() {
+        let v = Vector3r {
+            x_val: 2.0,
+            y_val: -3.0,
+            z_val: 4.0,
+        };
+        let scale = Vector3r {
+            x_val: 0.5,
+            y_val: 2.0,
+            z_val: -1.0,
+        };
+
+        let scaled = v.component_mul(&scale);
+        assert_eq!(
+            (scaled.x_val, scaled.y_val, scaled.z_val),
+            (1.0, -6.0, -4.0)
+        );
+        crate::assert_vec_approx_eq!(scaled.component_div(&scale), v);
+    }
+
+    #[test]
+    fn gps_data_exposes_the_fix() {
+        let mut data = GpsData::default();
+        data.gnss.geo_point = GeoPoint {
+            latitude: 47.6,
+            longitude: -122.1,
+            altitude: 120.5,
+        };
+        data.gnss.fix_type = GnssFixType::Fix3d;
+
+        let decoded = GpsData::try_from(Value::from(data)).unwrap();
+        assert_eq!(decoded.lat_lon_alt(), (47.6, -122.1, 120.5));
+        assert_eq!(decoded.gnss.fix_type, GnssFixType::Fix3d);
+        assert!(GnssFixType::try_from(Value::from(7u64)).is_err());
+    }
+
+    #[test]
+    fn non_string_keys_are_rejected() {
+        let value = Value::Map(vec![
+            ("x_val".into(), 1.0.into()),
+            ("y_val".into(), 2.0.into()),
+            (Value::from(3), 3.0.into()),
+        ]);
+
+        let error = Vector3r::try_from(value).unwrap_err();
+        assert!(error.to_string().contains("Vector3r"));
+    }
+
+    #[test]
+    fn pose_display() {
+        let pose = Pose::new(
+            Some(Vector3r {
+                x_val: 1.0,
+                y_val: 2.5,
+                z_val: -3.0,
+            }),
+            Some(Quaternionr {
+                w_val: 1.0,
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(pose.to_string(), "pos=(1,2.5,-3) quat=(1,0,0,0)");
+    }
+
+    #[test]
+    fn kinematics_row_matches_header() {
+        let state = KinematicsState {
+            orientation: Quaternionr {
+                w_val: 1.0,
+                z_val: 0.5,
+                ..Default::default()
+            },
+            angular_acceleration: Vector3r {
+                z_val: 9.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let row = state.to_row();
+        let header = KinematicsState::header();
+        assert_eq!(header[3], "orientation_w");
+        assert_eq!(row[3], 1.0);
+        assert_eq!(header[6], "orientation_z");
+        assert_eq!(row[6], 0.5);
+        assert_eq!(header[18], "angular_acceleration_z");
+        assert_eq!(row[18], 9.0);
+    }
+
+    #[test]
+    fn fsds_default_fills_missing_fields() {
+        #[derive(FromIntoValue, Debug)]
+        struct Partial {
+            position: Vector3r,
+            #[fsds(default)]
+            orientation: Quaternionr,
+        }
+
+        let value = Value::Map(vec![("position".into(), Vector3r::default().into())]);
+        let partial = Partial::try_from(value).unwrap();
+        assert_eq!(partial.orientation.w_val, 0.0);
+
+        let value = Value::Map(vec![("orientation".into(), Quaternionr::default().into())]);
+        assert!(Partial::try_from(value).is_err());
+    }
+
+    #[test]
+    fn hand_written_decoders_reject_extra_fields() {
+        let Value::Map(mut map) = Value::from(LidarData::default()) else {
+            unreachable!();
+        };
+        for (key, value) in map.iter_mut() {
+            if key.as_str() == Some("time_stamp") {
+                *value = Value::F64(7.0);
+            }
+        }
+        assert_eq!(
+            LidarData::try_from(Value::Map(map.clone()))
+                .unwrap()
+                .time_stamp,
+            7
+        );
+        map.push(("extra".into(), Value::Nil));
+        assert!(LidarData::try_from(Value::Map(map)).is_err());
+
+        let Value::Map(mut map) = Value::from(ImageResponse::default()) else {
+            unreachable!();
+        };
+        map.push(("extra".into(), Value::Nil));
+        assert!(ImageResponse::try_from(Value::Map(map)).is_err());
+    }
+
+    #[test]
+    fn referee_state_decodes_float_doo_counter() {
+        let value = Value::Map(vec![("doo_counter".into(), Value::F64(2.0))]);
+        assert_eq!(RefereeState::try_from(value).unwrap().doo_counter, 2);
+
+        let value = Value::Map(vec![("doo_counter".into(), Value::from(-1))]);
+        assert!(RefereeState::try_from(value).is_err());
+    }
+
+    #[test]
+    fn image_response_decodes_float_time_stamps() {
+        let Value::Map(mut map) = Value::from(ImageResponse::default()) else {
+            unreachable!();
+        };
+        for (key, value) in map.iter_mut() {
+            match key.as_str() {
+                Some("time_stamp") => *value = Value::F64(42.0),
+                Some("width") => *value = Value::F32(2.0),
+                _ => {}
+            }
+        }
+
+        let response = ImageResponse::try_from(Value::Map(map)).unwrap();
+        assert_eq!((response.time_stamp, response.width), (42, 2));
+    }
+
+    #[test]
+    fn image_response_channel_order() {
+        // A blue pixel then a red pixel, in the BGR order sent by the server.
+        let response = ImageResponse {
+            image_data_uint8: vec![255, 0, 0, 0, 0, 255],
+            compress: false,
+            width: 2,
+            height: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            response.to_bgra().unwrap(),
+            vec![255, 0, 0, 255, 0, 0, 255, 255]
+        );
+        assert_eq!(
+            response.to_rgba().unwrap(),
+            vec![0, 0, 255, 255, 255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn quaternion_xyzw_order() {
+        let q = Quaternionr {
+            w_val: 1.0,
+            x_val: 2.0,
+            y_val: 3.0,
+            z_val: 4.0,
+        };
+
+        assert_eq!(q.to_xyzw(), [2.0, 3.0, 4.0, 1.0]);
+        assert!(Quaternionr::from_xyzw(&q.to_xyzw()).approx_eq(&q, 0.0));
+    }
+
+    #[test]
+    fn image_length_mismatch_is_reported() {
+        let mut response = ImageResponse {
+            compress: false,
+            width: 2,
+            height: 2,
+            image_data_uint8: vec![0; 12],
+            ..Default::default()
+        };
+        assert_eq!(response.channels().unwrap(), 3);
+
+        response.image_data_uint8.truncate(10);
+        assert!(response.channels().is_err());
+        assert!(response.to_bgra().is_err());
+
+        response.pixels_as_float = true;
+        response.image_type = ImageType::DepthPerspective;
+        response.image_data_float = vec![0.0; 4];
+        assert_eq!(response.channels().unwrap(), 1);
+    }
+
+    #[test]
+    fn kinematics_diff() {
+        let previous = KinematicsState {
+            position: Vector3r {
+                x_val: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let current = KinematicsState {
+            position: Vector3r {
+                x_val: 1.5,
+                y_val: 2.0,
+                ..Default::default()
+            },
+            linear_velocity: Vector3r {
+                x_val: 3.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            current.diff(&previous).to_string(),
+            "pos=(0.5,2,0) quat=(0,0,0,0) lin_vel=(3,0,0) ang_vel=(0,0,0) lin_acc=(0,0,0) ang_acc=(0,0,0)"
+        );
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn nalgebra_isometry_round_trip() {
+        let pose = Pose::new(
+            Some(Vector3r {
+                x_val: 1.0,
+                y_val: 2.0,
+                z_val: 3.0,
+            }),
+            Some(Quaternionr::from_xyzw(&[
+                0.0,
+                0.0,
+                0.5f64.sin(),
+                0.5f64.cos(),
+            ])),
+        );
+
+        let isometry = nalgebra::Isometry3::from(pose);
+        let moved = isometry * nalgebra::Point3::new(1.0, 0.0, 0.0);
+        assert!((moved.x - (1.0 + 1.0f64.cos())).abs() < 1e-12);
+        assert!((moved.y - (2.0 + 1.0f64.sin())).abs() < 1e-12);
+
+        let back = Pose::from(isometry);
+        assert!(back.position.approx_eq(&pose.position, 1e-12));
+        assert!(back.orientation.approx_eq(&pose.orientation, 1e-12));
+    }
+
+    #[test]
+    fn depth_images_keep_their_kind() {
+        let mut response = ImageResponse {
+            image_type: ImageType::DepthPlanner,
+            pixels_as_float: true,
+            compress: false,
+            width: 2,
+            height: 1,
+            image_data_float: vec![1.5, 2.5],
+            ..Default::default()
+        };
+
+        let depth = response.to_depth().unwrap();
+        assert_eq!(depth.kind, DepthKind::Planar);
+        assert_eq!(depth.at(1, 0), Some(2.5));
+        assert_eq!(depth.at(0, 1), None);
+
+        response.image_type = ImageType::DepthPerspective;
+        assert_eq!(response.to_depth().unwrap().kind, DepthKind::Perspective);
+
+        response.image_type = ImageType::Scene;
+        assert!(response.to_depth().is_err());
+    }
+
+    #[test]
+    fn gps_ground_speed_is_horizontal() {
+        let gps = GpsData {
+            gnss: GnssReport {
+                velocity: Vector3r {
+                    x_val: 3.0,
+                    y_val: 4.0,
+                    z_val: -10.0,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let decoded = GpsData::try_from(Value::from(gps)).unwrap();
+        assert_eq!(decoded.ground_speed(), 5.0);
+    }
+
+    #[test]
+    fn spherical_and_cylindrical_round_trip() {
+        let v = Vector3r {
+            x_val: 1.0,
+            y_val: 1.0,
+            z_val: 2.0_f64.sqrt(),
+        };
+
+        let (r, theta, phi) = v.to_spherical();
+        assert!((r - 2.0).abs() < 1e-12);
+        assert!((theta - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        assert!((phi - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        assert!(Vector3r::from_spherical(r, theta, phi).approx_eq(&v, 1e-12));
+
+        let (rho, phi, z) = v.to_cylindrical();
+        assert!((rho - 2.0_f64.sqrt()).abs() < 1e-12);
+        assert!(Vector3r::from_cylindrical(rho, phi, z).approx_eq(&v, 1e-12));
+
+        assert_eq!(Vector3r::default().to_spherical(), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ground_truth_and_sensed_are_distinct_types() {
+        fn localize(imu: &Sensed<ImuData>) -> f64 {
+            imu.angular_velocity.z_val
+        }
+
+        let imu = Sensed::new(ImuData {
+            angular_velocity: Vector3r {
+                z_val: 0.5,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(localize(&imu), 0.5);
+
+        let truth = GroundTruth::new(KinematicsState::default());
+        assert_eq!(truth.into_inner().position.x_val, 0.0);
+    }
+
+    #[test]
+    fn yaw_rate_converts_like_the_imu() {
+        // Turning right, i.e. clockwise seen from above.
+        let yaw_rate = Vector3r {
+            z_val: 0.5,
+            ..Default::default()
+        };
+        let kinematics = KinematicsState {
+            angular_velocity: yaw_rate,
+            angular_acceleration: yaw_rate,
+            ..Default::default()
+        }
+        .ned_to_enu();
+        let imu = ImuData {
+            angular_velocity: yaw_rate,
+            ..Default::default()
+        }
+        .ned_to_enu();
+
+        assert_eq!(kinematics.angular_velocity.z_val, -0.5);
+        assert!(kinematics
+            .angular_velocity
+            .approx_eq(&imu.angular_velocity, 0.0));
+        assert!(kinematics
+            .angular_acceleration
+            .approx_eq(&imu.angular_velocity, 0.0));
+    }
+
+    #[test]
+    fn ned_to_enu_keeps_the_heading() {
+        let north = Vector3r {
+            x_val: 1.0,
+            ..Default::default()
+        };
+        let down = Vector3r {
+            z_val: 1.0,
+            ..Default::default()
+        };
+        assert!(north.ned_to_enu().approx_eq(
+            &Vector3r {
+                y_val: 1.0,
+                ..Default::default()
+            },
+            1e-12
+        ));
+        assert_eq!(down.ned_to_enu().z_val, -1.0);
+
+        // A car facing east in NED (yaw 90 deg) faces east in ENU (yaw 0).
+        let east = Quaternionr {
+            w_val: std::f64::consts::FRAC_1_SQRT_2,
+            z_val: std::f64::consts::FRAC_1_SQRT_2,
+            ..Default::default()
+        };
+        let identity = Quaternionr {
+            w_val: 1.0,
+            ..Default::default()
+        };
+        let enu = east.ned_to_enu();
+        assert!((enu.dot(&identity).abs() - 1.0).abs() < 1e-12);
+        assert!((enu.ned_to_enu().dot(&east).abs() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn telemetry_precision_is_opt_in() {
+        let state = KinematicsState {
+            position: Vector3r {
+                x_val: 1.23456,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(state.to_string().starts_with("pos=(1.23456,0,0)"));
+        assert!(format!("{:.2}", state).starts_with("pos=(1.23,0.00,0.00)"));
+        assert_eq!(state.to_row_rounded(3)[0], 1.235);
+    }
+
+    #[test]
+    fn empty_lidar_scans_are_valid() {
+        let empty = Value::Map(vec![
+            ("point_cloud".into(), Value::Array(vec![])),
+            ("time_stamp".into(), 5.into()),
+            ("pose".into(), Pose::default().into()),
+        ]);
+        let data = LidarData::try_from(empty).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(data.points().len(), 0);
+
+        let no_hit = LidarData {
+            point_cloud: vec![0.0],
+            ..Default::default()
+        };
+        let data = LidarData::try_from(Value::from(no_hit)).unwrap();
+        assert!(data.is_empty());
+        assert_eq!(data.points().len(), 0);
+
+        let one = LidarData {
+            point_cloud: vec![1.0, 2.0, 3.0],
+            ..Default::default()
+        };
+        assert_eq!(one.points()[0].z_val, 3.0);
+    }
+
+    #[test]
+    fn heading_is_the_yaw() {
+        let yaw: f64 = 2.0;
+        let state = KinematicsState {
+            orientation: Quaternionr {
+                w_val: (yaw / 2.0).cos(),
+                z_val: (yaw / 2.0).sin(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!((state.heading() - yaw).abs() < 1e-12);
+        assert!((state.heading_deg() - yaw.to_degrees()).abs() < 1e-9);
+    }
+}
@@ -1 +1,563 @@
-// TODO:
+//! This module contains helpers built on top of the types exchanged with the
+//! simulator, such as evaluation utilities for perception pipelines.
+
+use crate::types::{CarState, ImuData, Pose, Position2D, Quaternionr, Vector3r};
+
+/// Match detected cones against ground-truth cones.
+///
+/// Returns the `(detected_index, truth_index)` pairs whose distance is at most
+/// `max_dist`. Matching is greedy: the closest pair overall is matched first,
+/// then the closest among the remaining cones, and so on. Every cone appears
+/// in at most one pair.
+///
+/// Both slices must be expressed in the same reference frame.
+pub fn match_cones(
+    detected: &[Vector3r],
+    truth: &[Vector3r],
+    max_dist: f64,
+) -> Vec<(usize, usize)> {
+    // Squared distances sort the same way, without a square root per pair.
+    let max_dist_squared = max_dist * max_dist;
+    let mut candidates = Vec::new();
+    for (i, d) in detected.iter().enumerate() {
+        for (j, t) in truth.iter().enumerate() {
+            let dist_squared = d.distance_squared_to(t);
+            if dist_squared <= max_dist_squared {
+                candidates.push((dist_squared, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut detected_used = vec![false; detected.len()];
+    let mut truth_used = vec![false; truth.len()];
+    let mut matches = Vec::new();
+    for (_, i, j) in candidates {
+        if !detected_used[i] && !truth_used[j] {
+            detected_used[i] = true;
+            truth_used[j] = true;
+            matches.push((i, j));
+        }
+    }
+
+    matches
+}
+
+/// Exponentially weighted moving average of a scalar signal.
+///
+/// Each update computes `alpha * sample + (1 - alpha) * previous`, so `alpha`
+/// is the weight of the newest sample: `1.0` disables smoothing, values close
+/// to `0.0` smooth heavily. The first sample initializes the average as-is.
+#[derive(Clone, Copy, Debug)]
+pub struct Ewma {
+    pub alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    /// Creates a new filter, `alpha` is clamped to `[0, 1]`.
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// Feed a new sample and return the updated average.
+    pub fn update(&mut self, sample: f64) -> f64 {
+        let value = match self.value {
+            Some(previous) => self.alpha * sample + (1.0 - self.alpha) * previous,
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    /// Feed the speed of a car state and return the smoothed speed.
+    pub fn update_speed(&mut self, state: &CarState) -> f64 {
+        self.update(state.speed)
+    }
+
+    /// The current average, `None` before the first sample.
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+
+    /// Forget every sample seen so far.
+    pub fn reset(&mut self) {
+        self.value = None;
+    }
+}
+
+/// Complementary filter fusing IMU angular velocity with the measured
+/// orientation.
+///
+/// Each update integrates the angular velocity over the time elapsed since
+/// the previous sample, then moves the prediction towards the measured
+/// orientation: `alpha` is the weight of the integrated gyro, e.g. `0.98`
+/// trusts the gyro in the short term and lets the measurement correct the
+/// drift. The first sample initializes the estimate to its orientation.
+#[derive(Clone, Copy, Debug)]
+pub struct ComplementaryFilter {
+    pub alpha: f64,
+    /// Current estimate and time stamp of the last sample.
+    state: Option<(Quaternionr, u64)>,
+}
+
+impl ComplementaryFilter {
+    /// Creates a new filter, `alpha` is clamped to `[0, 1]`.
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            state: None,
+        }
+    }
+
+    /// Feed a new sample and return the fused orientation.
+    pub fn update(&mut self, imu: &ImuData) -> Quaternionr {
+        let orientation = match self.state {
+            Some((previous, time_stamp)) => {
+                let dt = imu.time_stamp.saturating_sub(time_stamp) as f64 / 1e9;
+                let predicted = (previous * Self::rotation(&imu.angular_velocity, dt)).sgn();
+                predicted.slerp(&imu.orientation, 1.0 - self.alpha)
+            }
+            None => imu.orientation,
+        };
+        self.state = Some((orientation, imu.time_stamp));
+        orientation
+    }
+
+    /// The current estimate, `None` before the first sample.
+    pub fn orientation(&self) -> Option<Quaternionr> {
+        self.state.map(|(orientation, _)| orientation)
+    }
+
+    /// Forget every sample seen so far.
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    /// Rotation by the body-frame angular velocity `omega` during `dt`.
+    fn rotation(omega: &Vector3r, dt: f64) -> Quaternionr {
+        let rate = omega.get_length();
+        if rate == 0.0 {
+            return Quaternionr {
+                w_val: 1.0,
+                ..Default::default()
+            };
+        }
+
+        let half_angle = rate * dt / 2.0;
+        let scale = half_angle.sin() / rate;
+        Quaternionr {
+            w_val: half_angle.cos(),
+            x_val: omega.x_val * scale,
+            y_val: omega.y_val * scale,
+            z_val: omega.z_val * scale,
+        }
+    }
+}
+
+/// A 2D k-d tree over cone positions, e.g. the referee cones, for fast
+/// nearest neighbour queries.
+///
+/// Queries return indices into the slice the map was built from, along with
+/// the distance in meters.
+#[derive(Clone, Debug)]
+pub struct ConeMap {
+    cones: Vec<Position2D>,
+    /// Cone indices laid out as a tree: the median of each slice is its root,
+    /// split on x at even depths and on y at odd depths.
+    tree: Vec<usize>,
+}
+
+impl ConeMap {
+    pub fn from_cones(cones: &[Position2D]) -> Self {
+        let mut tree: Vec<usize> = (0..cones.len()).collect();
+        Self::build(cones, &mut tree, 0);
+
+        Self {
+            cones: cones.to_vec(),
+            tree,
+        }
+    }
+
+    /// The closest cone to `point` and its distance, `None` if the map is
+    /// empty.
+    pub fn nearest(&self, point: &Position2D) -> Option<(usize, f64)> {
+        let mut best = None;
+        self.nearest_in(&self.tree, 0, point, &mut best);
+        best.map(|(index, dist_sq): (usize, f64)| (index, dist_sq.sqrt()))
+    }
+
+    /// Every cone at most `radius` from `point`, closest first.
+    pub fn within_radius(&self, point: &Position2D, radius: f64) -> Vec<(usize, f64)> {
+        let mut found = Vec::new();
+        self.within_radius_in(&self.tree, 0, point, radius, &mut found);
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    fn build(cones: &[Position2D], tree: &mut [usize], depth: usize) {
+        if tree.len() <= 1 {
+            return;
+        }
+        let mid = tree.len() / 2;
+        tree.select_nth_unstable_by(mid, |a, b| {
+            Self::coordinate(&cones[*a], depth).total_cmp(&Self::coordinate(&cones[*b], depth))
+        });
+        let (left, right) = tree.split_at_mut(mid);
+        Self::build(cones, left, depth + 1);
+        Self::build(cones, &mut right[1..], depth + 1);
+    }
+
+    fn nearest_in(
+        &self,
+        tree: &[usize],
+        depth: usize,
+        point: &Position2D,
+        best: &mut Option<(usize, f64)>,
+    ) {
+        if tree.is_empty() {
+            return;
+        }
+        let mid = tree.len() / 2;
+        let cone = &self.cones[tree[mid]];
+        let dist_sq = Self::distance_squared(cone, point);
+        if best.is_none_or(|(_, best_sq)| dist_sq < best_sq) {
+            *best = Some((tree[mid], dist_sq));
+        }
+
+        let offset = Self::coordinate(point, depth) - Self::coordinate(cone, depth);
+        let (near, far) = if offset < 0.0 {
+            (&tree[..mid], &tree[mid + 1..])
+        } else {
+            (&tree[mid + 1..], &tree[..mid])
+        };
+        self.nearest_in(near, depth + 1, point, best);
+        if best.is_none_or(|(_, best_sq)| offset * offset < best_sq) {
+            self.nearest_in(far, depth + 1, point, best);
+        }
+    }
+
+    fn within_radius_in(
+        &self,
+        tree: &[usize],
+        depth: usize,
+        point: &Position2D,
+        radius: f64,
+        found: &mut Vec<(usize, f64)>,
+    ) {
+        if tree.is_empty() {
+            return;
+        }
+        let mid = tree.len() / 2;
+        let cone = &self.cones[tree[mid]];
+        let dist = Self::distance_squared(cone, point).sqrt();
+        if dist <= radius {
+            found.push((tree[mid], dist));
+        }
+
+        let offset = Self::coordinate(point, depth) - Self::coordinate(cone, depth);
+        if offset - radius <= 0.0 {
+            self.within_radius_in(&tree[..mid], depth + 1, point, radius, found);
+        }
+        if offset + radius >= 0.0 {
+            self.within_radius_in(&tree[mid + 1..], depth + 1, point, radius, found);
+        }
+    }
+
+    fn coordinate(point: &Position2D, depth: usize) -> f64 {
+        if depth.is_multiple_of(2) {
+            point.x_val
+        } else {
+            point.y_val
+        }
+    }
+
+    fn distance_squared(a: &Position2D, b: &Position2D) -> f64 {
+        (a.x_val - b.x_val).powi(2) + (a.y_val - b.y_val).powi(2)
+    }
+}
+
+/// Compute the track centerline from the blue (left) and yellow (right)
+/// cones.
+///
+/// Each blue cone is paired with its nearest yellow cone and the midpoints
+/// are chained from nearest to nearest, starting from the midpoint of the
+/// first blue cone: put a cone close to the start line first to get the
+/// centerline in driving order. Returns an empty vector if either side has
+/// no cones.
+pub fn centerline(blue: &[Position2D], yellow: &[Position2D]) -> Vec<Position2D> {
+    let yellow_map = ConeMap::from_cones(yellow);
+    let mut midpoints: Vec<Position2D> = blue
+        .iter()
+        .filter_map(|b| {
+            let (j, _) = yellow_map.nearest(b)?;
+            Some(Position2D {
+                x_val: (b.x_val + yellow[j].x_val) / 2.0,
+                y_val: (b.y_val + yellow[j].y_val) / 2.0,
+            })
+        })
+        .collect();
+
+    let mut ordered = Vec::with_capacity(midpoints.len());
+    let mut current = match midpoints.first() {
+        Some(first) => *first,
+        None => return ordered,
+    };
+    while !midpoints.is_empty() {
+        let (closest, _) = midpoints
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                (
+                    i,
+                    (p.x_val - current.x_val).powi(2) + (p.y_val - current.y_val).powi(2),
+                )
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        current = midpoints.swap_remove(closest);
+        ordered.push(current);
+    }
+
+    ordered
+}
+
+/// Signed distance in meters from `point` to the closest segment of
+/// `centerline`, positive on the left of the driving direction and negative
+/// on its right, as seen from above in the simulator NED frame.
+///
+/// The driving direction is the order of the centerline points, as returned
+/// by `centerline`. Returns `None` if the centerline has less than two
+/// points.
+pub fn lateral_offset(point: &Position2D, centerline: &[Position2D]) -> Option<f64> {
+    centerline
+        .windows(2)
+        .filter_map(|segment| {
+            let (a, b) = (segment[0], segment[1]);
+            let (dx, dy) = (b.x_val - a.x_val, b.y_val - a.y_val);
+            let length_squared = dx * dx + dy * dy;
+            if length_squared == 0.0 {
+                return None;
+            }
+            let (px, py) = (point.x_val - a.x_val, point.y_val - a.y_val);
+            let t = ((px * dx + py * dy) / length_squared).clamp(0.0, 1.0);
+            let distance = ((px - t * dx).powi(2) + (py - t * dy).powi(2)).sqrt();
+            // With z pointing down, the left side has a negative cross product.
+            let side = if dx * py - dy * px > 0.0 { -1.0 } else { 1.0 };
+            Some(side * distance)
+        })
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+}
+
+/// Whether `car` is between the blue (left) and yellow (right) cones.
+///
+/// The car is within the track if its distance from the centerline is at
+/// most the distance from the centerline of the nearest cone on the same
+/// side. Returns false if there are not enough cones to build a centerline
+/// of at least two points.
+pub fn is_within_track(car: &Position2D, left: &[Position2D], right: &[Position2D]) -> bool {
+    let line = centerline(left, right);
+    let nearest_offset = |cones: &[Position2D]| {
+        let (i, _) = ConeMap::from_cones(cones).nearest(car)?;
+        lateral_offset(&cones[i], &line)
+    };
+    let (Some(offset), Some(left_offset), Some(right_offset)) = (
+        lateral_offset(car, &line),
+        nearest_offset(left),
+        nearest_offset(right),
+    ) else {
+        return false;
+    };
+
+    // Compare sides with the cones rather than the sign convention, so that
+    // cones listed in the opposite driving direction still work.
+    let limit = if offset * left_offset >= 0.0 {
+        left_offset
+    } else {
+        right_offset
+    };
+    offset.abs() <= limit.abs()
+}
+
+/// Signed angle in radians, in `(-pi, pi]`, from the forward axis of the car
+/// to the direction of `target`, seen from above.
+///
+/// Positive when the target is on the left of the car, i.e. when the car
+/// must steer left, as in a pure pursuit or Stanley controller. Heights are
+/// ignored. Note that positive `CarControls::steering` turns right: command
+/// `-k * steering_error(...)` to steer towards the target.
+pub fn steering_error(car_pose: &Pose, target: &Vector3r) -> f64 {
+    let direction =
+        (target.y_val - car_pose.position.y_val).atan2(target.x_val - car_pose.position.x_val);
+    let error = car_pose.orientation.yaw() - direction;
+    // Wrap to (-pi, pi].
+    let wrapped = error.rem_euclid(std::f64::consts::TAU);
+    if wrapped > std::f64::consts::PI {
+        wrapped - std::f64::consts::TAU
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cone(x: f64, y: f64) -> Vector3r {
+        Vector3r {
+            x_val: x,
+            y_val: y,
+            z_val: 0.0,
+        }
+    }
+
+    #[test]
+    fn match_cones_is_greedy_and_one_to_one() {
+        let detected = [cone(0.1, 0.0), cone(5.0, 0.0), cone(0.0, 0.3)];
+        let truth = [cone(0.0, 0.0), cone(10.0, 0.0)];
+
+        let matches = match_cones(&detected, &truth, 1.0);
+
+        assert_eq!(matches, vec![(0, 0)]);
+    }
+
+    #[test]
+    fn ewma_weights_the_newest_sample_by_alpha() {
+        let mut ewma = Ewma::new(0.25);
+        assert_eq!(ewma.update(4.0), 4.0);
+        assert_eq!(ewma.update(8.0), 5.0);
+        assert_eq!(ewma.update(5.0), 5.0);
+
+        ewma.reset();
+        assert_eq!(ewma.value(), None);
+    }
+
+    fn yaw(angle: f64) -> Quaternionr {
+        Quaternionr {
+            w_val: (angle / 2.0).cos(),
+            z_val: (angle / 2.0).sin(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn complementary_filter_integrates_and_corrects() {
+        // 1 rad/s yaw rate sampled at 100 Hz for one second.
+        let samples = |orientation: fn(f64) -> Quaternionr| {
+            (0..=100).map(move |i| ImuData {
+                time_stamp: i * 10_000_000,
+                orientation: orientation(i as f64 / 100.0),
+                angular_velocity: Vector3r {
+                    z_val: 1.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        };
+
+        // Pure integration follows the gyro.
+        let mut gyro_only = ComplementaryFilter::new(1.0);
+        let mut estimate = Quaternionr::default();
+        for imu in samples(|_| yaw(0.0)) {
+            estimate = gyro_only.update(&imu);
+        }
+        assert!(estimate.approx_eq(&yaw(1.0), 1e-9));
+
+        // A stuck orientation measurement pulls the estimate back.
+        let mut fused = ComplementaryFilter::new(0.9);
+        for imu in samples(|_| yaw(0.0)) {
+            estimate = fused.update(&imu);
+        }
+        assert!(estimate.w_val > yaw(0.2).w_val);
+    }
+
+    #[test]
+    fn cone_map_matches_brute_force() {
+        let cones: Vec<Position2D> = (0..200)
+            .map(|i| Position2D {
+                x_val: ((i * 37) % 101) as f64 * 0.7,
+                y_val: ((i * 53) % 97) as f64 * 0.3,
+            })
+            .collect();
+        let map = ConeMap::from_cones(&cones);
+        let distance = |a: &Position2D, b: &Position2D| {
+            ((a.x_val - b.x_val).powi(2) + (a.y_val - b.y_val).powi(2)).sqrt()
+        };
+
+        for query in [(0.0, 0.0), (35.0, 14.5), (100.0, -3.0), (12.3, 20.1)] {
+            let query = Position2D {
+                x_val: query.0,
+                y_val: query.1,
+            };
+            let closest = cones
+                .iter()
+                .map(|cone| distance(cone, &query))
+                .fold(f64::INFINITY, f64::min);
+            assert_eq!(map.nearest(&query).unwrap().1, closest);
+
+            let expected = cones
+                .iter()
+                .filter(|cone| distance(cone, &query) <= 5.0)
+                .count();
+            assert_eq!(map.within_radius(&query, 5.0).len(), expected);
+        }
+        assert!(ConeMap::from_cones(&[])
+            .nearest(&Position2D::default())
+            .is_none());
+    }
+
+    #[test]
+    fn centerline_of_a_straight() {
+        let point = |x: f64, y: f64| Position2D { x_val: x, y_val: y };
+        // Cones listed out of order, the yellow side is 3 m wide to the right.
+        let blue = [point(0.0, 1.5), point(10.0, 1.5), point(5.0, 1.5)];
+        let yellow = [point(5.0, -1.5), point(0.0, -1.5), point(10.0, -1.5)];
+
+        let line: Vec<_> = centerline(&blue, &yellow)
+            .iter()
+            .map(|p| (p.x_val, p.y_val))
+            .collect();
+
+        assert_eq!(line, [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)]);
+        assert!(centerline(&blue, &[]).is_empty());
+    }
+
+    #[test]
+    fn track_limits_on_a_straight() {
+        let point = |x_val, y_val| Position2D { x_val, y_val };
+        // Driving north, the blue cones are on the west (left) side.
+        let blue: Vec<_> = (0..6).map(|i| point(2.0 * i as f64, -2.0)).collect();
+        let yellow: Vec<_> = (0..6).map(|i| point(2.0 * i as f64, 2.0)).collect();
+
+        let line = centerline(&blue, &yellow);
+        assert_eq!(lateral_offset(&point(5.0, -1.0), &line), Some(1.0));
+        assert_eq!(lateral_offset(&point(5.0, 0.5), &line), Some(-0.5));
+        assert_eq!(lateral_offset(&point(5.0, 0.5), &line[..1]), None);
+
+        assert!(is_within_track(&point(5.0, 0.0), &blue, &yellow));
+        assert!(is_within_track(&point(5.0, 1.5), &blue, &yellow));
+        assert!(is_within_track(&point(5.0, -1.9), &blue, &yellow));
+        assert!(!is_within_track(&point(5.0, 3.0), &blue, &yellow));
+        assert!(!is_within_track(&point(5.0, -2.5), &blue, &yellow));
+        assert!(!is_within_track(&point(5.0, 0.0), &blue, &[]));
+    }
+
+    #[test]
+    fn steering_error_is_left_positive() {
+        // Facing east (+y) from (1, 1).
+        let pose = Pose::new(Some(cone(1.0, 1.0)), Some(yaw(std::f64::consts::FRAC_PI_2)));
+
+        assert!(steering_error(&pose, &cone(1.0, 5.0)).abs() < 1e-12);
+        // North is on the left when facing east.
+        let left = steering_error(&pose, &cone(5.0, 5.0));
+        assert!((left - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        let right = steering_error(&pose, &cone(-3.0, 5.0));
+        assert!((right + std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+        // Behind, the error wraps instead of exceeding pi.
+        let behind = steering_error(&pose, &cone(0.0, -3.0));
+        assert!(behind.abs() <= std::f64::consts::PI && behind < 0.0);
+    }
+}
@@ -0,0 +1,118 @@
+//! This module contains `Watchdog`, which stops the car when the control loop
+//! stalls.
+//!
+//! The control loop calls `Watchdog::pulse` every time it sends new controls.
+//! If no pulse arrives within the timeout, a background task commands the car
+//! to a safe state with `FSDSClient::emergency_stop`, like the hardware
+//! watchdogs of real Formula Student cars.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::FSDSClient;
+
+pub struct Watchdog {
+    heartbeat: Arc<Notify>,
+    trips: Arc<AtomicU64>,
+    stop: CancellationToken,
+}
+
+impl Watchdog {
+    /// Start watching `vehicle_name`, using a clone of `client` to stop it.
+    ///
+    /// The timeout starts right away, so send the first controls (and pulse)
+    /// within `timeout`. The watchdog stops when dropped.
+    pub fn spawn(client: &FSDSClient, vehicle_name: &str, timeout: Duration) -> Self {
+        let heartbeat = Arc::new(Notify::new());
+        let trips = Arc::new(AtomicU64::new(0));
+        let stop = CancellationToken::new();
+
+        let mut client = client.clone();
+        let vehicle_name = vehicle_name.to_string();
+        let (task_heartbeat, task_trips, task_stop) =
+            (heartbeat.clone(), trips.clone(), stop.clone());
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = task_stop.cancelled() => break,
+                    _ = task_heartbeat.notified() => continue,
+                    _ = tokio::time::sleep(timeout) => {}
+                }
+
+                // Stalled: stop the car once, then wait for the loop to resume.
+                if let Err(e) = client.emergency_stop(&vehicle_name).await {
+                    tracing::warn!("Could not stop {}: {}", vehicle_name, e);
+                }
+                task_trips.fetch_add(1, Ordering::Relaxed);
+                tokio::select! {
+                    _ = task_stop.cancelled() => break,
+                    _ = task_heartbeat.notified() => {}
+                }
+            }
+        });
+
+        Self {
+            heartbeat,
+            trips,
+            stop,
+        }
+    }
+
+    /// Signal that the control loop is alive, typically right after
+    /// `set_car_controls`.
+    pub fn pulse(&self) {
+        self.heartbeat.notify_one();
+    }
+
+    /// Number of times the watchdog stopped the car.
+    pub fn trips(&self) -> u64 {
+        self.trips.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use msgpack_rpc::Value;
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_loop_is_stopped_once() {
+        let server = MockServer::start(|_, _| (Duration::ZERO, Ok(Value::Nil))).await;
+        let client = FSDSClient::init(Some(&server.addr()), None).await.unwrap();
+        let watchdog = Watchdog::spawn(&client, "FSCar", Duration::from_millis(50));
+
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            watchdog.pulse();
+        }
+        assert_eq!(watchdog.trips(), 0);
+
+        // The clock keeps advancing while the stop request is in flight, so
+        // wait for the trip rather than for a fixed time.
+        let tripped = async {
+            while watchdog.trips() == 0 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(60), tripped)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(watchdog.trips(), 1);
+    }
+}